@@ -0,0 +1,9 @@
+# The default 'admin' group did not get any permissions during initialization.
+# Give it every permission known so far, including the new one to edit the
+# Pokédex progress of other users.
+insert into `group_permission` values
+    ('admin', 'add_role'),
+    ('admin', 'remove_role'),
+    ('admin', 'add_pokedex_to_other_profiles'),
+    ('admin', 'remove_pokedex_from_other_profiles'),
+    ('admin', 'edit_pokedex_progress_of_other_profiles');
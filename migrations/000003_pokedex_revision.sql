@@ -0,0 +1,20 @@
+# Every version of a pokedex definition that was applied to this instance.
+create table if not exists `pokedex_revision` (
+    `pokedex_id` varchar(256) not null,
+    # Counts up by one with every change to the definition of the pokedex.
+    `revision` integer not null,
+    # SHA-256 of the definition's content. Used to determine if the data is outdated.
+    `content_hash` char(64) not null,
+    # The commit the definition was compiled from. This is only informational.
+    `commit_hash` varchar(64) not null,
+    `applied_at` timestamp default current_timestamp not null,
+    primary key (`pokedex_id`, `revision`),
+    foreign key (`pokedex_id`) references `pokedex` (`id`)
+);
+
+# The revision each pokedex is currently at.
+# Existing pokedexes start at revision 0 with an empty hash, so they
+# get their first proper revision on the next update.
+alter table `pokedex`
+    add column `revision` integer not null default 0,
+    add column `content_hash` char(64) not null default '';
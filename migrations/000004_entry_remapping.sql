@@ -0,0 +1,15 @@
+# Progress on entries that no longer exist after a pokedex definition changed.
+# Rows are moved here instead of being deleted, so they can be restored by hand
+# if a definition update went wrong.
+create table if not exists `orphaned_pokedex_progress` (
+    `user_id` integer not null,
+    `pokedex_id` varchar(256) not null,
+    `entry_id` integer not null,
+    # The revision that removed the entry.
+    `revision` integer not null,
+    primary key (`user_id`, `pokedex_id`, `entry_id`, `revision`)
+);
+
+# The IDs of the entries that were orphaned when the revision was applied.
+alter table `pokedex_revision`
+    add column `orphaned_entries` json null;
@@ -0,0 +1,2 @@
+# Allow the default 'admin' group to reload pokedex definitions at runtime.
+insert into `group_permission` values ('admin', 'manage_pokedexes');
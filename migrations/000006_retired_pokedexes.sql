@@ -0,0 +1,5 @@
+# Pokedexes whose definition file was removed are retired instead of deleted.
+# Users can't add them to their profile anymore, but keep read-only access
+# to their progress.
+alter table `pokedex`
+    add column `retired` boolean not null default false;
@@ -0,0 +1,9 @@
+# How marking an entry affects entries of the same species in the other pokedexes
+# of a user. 'offer' asks the user, 'auto' marks them right away.
+alter table `user`
+    add column `species_link` enum('off', 'offer', 'auto') not null default 'off';
+
+# The pokedex whose entry caused this entry to be marked through a species link.
+# Null for entries the user marked directly.
+alter table `user_pokedex_progress`
+    add column `source_pokedex_id` varchar(256) null;
@@ -0,0 +1,11 @@
+# Who may see the profile and progress of a user.
+alter table `user`
+    add column `visibility` enum('public', 'logged_in', 'private') not null default 'public';
+
+# Overrides the visibility of the profile for a single pokedex.
+# Null means the pokedex is as visible as the profile.
+alter table `user_pokedex`
+    add column `visibility` enum('public', 'logged_in', 'private') null;
+
+# Allow the default 'admin' group to see hidden profiles.
+insert into `group_permission` values ('admin', 'view_hidden_profiles');
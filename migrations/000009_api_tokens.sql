@@ -0,0 +1,14 @@
+# Personal API tokens for clients that can't log in through the browser.
+create table if not exists `api_token` (
+    `token_id` integer primary key auto_increment,
+    `user_id` integer not null,
+    # A name the user chose to tell their tokens apart.
+    `name` varchar(128) not null,
+    # SHA-256 of the token in hex. The token itself is only shown once after creation.
+    `token_hash` char(64) unique not null,
+    # 'full' may do everything the user may do, the others restrict that.
+    `scope` enum('full', 'progress_write', 'read_only') not null,
+    `creation_date` timestamp default current_timestamp not null,
+    `last_used` timestamp null,
+    index `user_tokens` (`user_id`)
+);
@@ -0,0 +1,16 @@
+# Failed login attempts, counted per account and per client IP address.
+create table if not exists `login_throttle` (
+    `kind` enum('account', 'ip') not null,
+    # The lowercase username for 'account', the IP address for 'ip'.
+    # Accounts that don't exist are tracked as well, so lockouts don't reveal which exist.
+    `subject` varchar(64) not null,
+    # Failed attempts since the counter was last reset.
+    `failures` integer not null,
+    `last_failure` timestamp default current_timestamp not null,
+    # Logins are rejected without checking the password until then.
+    `locked_until` timestamp null,
+    primary key (`kind`, `subject`)
+);
+
+# Allow the default 'admin' group to see and clear lockouts.
+insert into `group_permission` values ('admin', 'manage_lockouts');
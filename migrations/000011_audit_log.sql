@@ -0,0 +1,20 @@
+# Security-relevant actions like logins and changes to other users' profiles.
+# Users are stored by name instead of ID, so records outlive renamed and deleted accounts.
+create table if not exists `audit_log` (
+    `audit_id` bigint primary key auto_increment,
+    `time` timestamp default current_timestamp not null,
+    `action` varchar(64) not null,
+    # The user who acted. For failed logins, this is the username that was tried.
+    `actor` varchar(64) not null,
+    # The user or group that was acted upon, if it isn't the actor.
+    `target` varchar(128) null,
+    # What exactly was done, e.g., which pokedex was added.
+    `details` varchar(256) null,
+    `ip` varchar(64) null,
+    `user_agent` varchar(512) null,
+    index `audit_actor` (`actor`),
+    index `audit_target` (`target`)
+);
+
+# Allow the default 'admin' group to view the audit log.
+insert into `group_permission` values ('admin', 'view_audit_log');
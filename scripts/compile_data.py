@@ -136,12 +136,17 @@ def create_update_record(path: Path, sprite_indices: dict[str, int]):
             continue
         entries.append({
             "id": entry["id"],
+            "key": entry.get("key"),
+            # Links entries of the same species across pokedexes.
+            # Defaults to the name, which is shared by all forms of a species.
+            "species": entry.get("species", make_name_id(entry["name"])),
             "name": entry["name"],
             "form": entry.get("form"),
             "sprite": sprite_indices[entry["sprite"]],
             "shiny": entry.get("shiny", False),
             "gmax": entry.get("gmax", False),
             "technical": entry.get("technical", False),
+            "gender": entry.get("gender"),
         })
 
     jsn = {
@@ -155,6 +160,9 @@ def create_update_record(path: Path, sprite_indices: dict[str, int]):
         "spritesheet_url": f"/image/{make_name_id(pokedex['name'])}-spritesheet.avif",
         "commit_hash": commit_hash,
         "entries": entries,
+        # Maps entry IDs of earlier versions to their new IDs.
+        # JSON object keys are strings, the server parses them as integers.
+        "remap": pokedex.get("remap", {}),
     }
     out_path = Path(f"data/pokedexes/{make_name_id(pokedex['name'])}.json")
     if not out_path.parent.exists():
@@ -177,6 +185,8 @@ def sanity_check_pokedex_definitions():
         assert len(pokedex["entries"]) > 0, "Empty pokedex"
         ids = list(x["id"] for x in pokedex["entries"] if x is not None)
         assert len(set(ids)) == len(ids), "Non-unique IDs in pokedex"
+        keys = list(x["key"] for x in pokedex["entries"] if x is not None and "key" in x)
+        assert len(set(keys)) == len(keys), "Non-unique keys in pokedex"
 
 
 if __name__ == "__main__":
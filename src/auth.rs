@@ -1,13 +1,25 @@
 use std::collections::HashSet;
 use std::fmt::{Debug, Formatter};
+use std::path::Path;
+use std::sync::Arc;
 use async_trait::async_trait;
 use axum_login::{AuthUser, AuthnBackend, AuthzBackend, UserId};
+use base64::Engine;
+use http::Method;
+use anyhow::Context;
+use argon2::{Algorithm, Argon2, Params, PasswordHasher, Version};
+use argon2::password_hash::SaltString;
 use password_auth::verify_password;
 use password_hash::PasswordHash;
+use rand::rngs::OsRng;
+use rand::RngCore;
 use serde::{Deserialize, Serialize};
-use sqlx::{query_as, FromRow, MySqlPool};
+use sha2::{Digest, Sha256};
+use sqlx::{query, query_as, FromRow, MySqlPool};
 use time::UtcDateTime;
 use tokio::task;
+use tracing::info;
+use utoipa::ToSchema;
 use crate::error::AppError;
 
 /// Unsafe version of the user struct that contains the actual password hash.
@@ -93,22 +105,294 @@ impl AuthUser for User {
 }
 
 
-#[derive(Debug, Clone, Deserialize)]
+#[derive(Debug, Clone, Deserialize, ToSchema)]
 pub struct Credentials {
     pub username: String,
     pub password: String,
     pub next: Option<String>,
+    /// Set by the "remember me" checkbox of the login form.
+    pub remember: Option<String>,
 }
 
+/// Hashes passwords with argon2id and the configured parameters.
 #[derive(Clone)]
-pub struct AuthBackend(MySqlPool);
+pub struct Argon2Hasher {
+    params: Params,
+}
+
+impl Argon2Hasher {
+    /// `memory_cost` is in KiB.
+    pub fn new(memory_cost: u32, time_cost: u32, parallelism: u32) -> anyhow::Result<Self> {
+        let params = Params::new(memory_cost, time_cost, parallelism, None)
+            .map_err(|err| anyhow::anyhow!("Invalid argon2 parameters: {}", err))?;
+        Ok(Self { params })
+    }
+
+    fn hash(&self, password: &str) -> Result<String, AppError> {
+        let salt = SaltString::generate(&mut OsRng);
+        let hash = Argon2::new(Algorithm::Argon2id, Version::V0x13, self.params.clone())
+            .hash_password(password.as_bytes(), &salt)
+            .map_err(|err| anyhow::anyhow!("Failed to hash password: {}", err))?;
+        Ok(hash.to_string())
+    }
+
+    /// Checks if a hash was made with another algorithm or other parameters than
+    /// the configured ones, so it should be replaced.
+    fn is_outdated(&self, hash: &str) -> bool {
+        let Ok(hash) = PasswordHash::new(hash) else {
+            return true;
+        };
+        let Ok(params) = Params::try_from(&hash) else {
+            return true;
+        };
+        hash.algorithm != Algorithm::Argon2id.ident()
+            || hash.version != Some(Version::V0x13 as u32)
+            || params.m_cost() != self.params.m_cost()
+            || params.t_cost() != self.params.t_cost()
+            || params.p_cost() != self.params.p_cost()
+    }
+}
+
+/// The outcome of checking a password against its hash.
+enum Verification {
+    Rejected,
+    Accepted,
+    /// The password is right, but its hash is outdated. This is the new hash.
+    Rehashed(String),
+}
+
+/// Requirements for new passwords.
+pub struct PasswordPolicy {
+    min_length: usize,
+    max_length: usize,
+    /// Passwords known from breaches. These are the first ones attackers try.
+    breached: HashSet<String>,
+}
+
+impl PasswordPolicy {
+    pub fn new(min_length: usize, max_length: usize) -> Self {
+        Self { min_length, max_length, breached: HashSet::new() }
+    }
+
+    /// Rejects the passwords of a wordlist with one password per line.
+    /// The list is kept in memory, so it should only contain the most common passwords.
+    pub fn with_breached_list(mut self, path: &Path) -> anyhow::Result<Self> {
+        let list = std::fs::read(path)
+            .with_context(|| format!("Failed to read the breached password list {}", path.display()))?;
+        self.breached = list
+            .split(|byte| *byte == b'\n')
+            .map(|line| String::from_utf8_lossy(line).trim_end_matches('\r').to_string())
+            .filter(|password| !password.is_empty())
+            .collect();
+        Ok(self)
+    }
+
+    /// The number of passwords in the breached list.
+    pub fn num_breached(&self) -> usize {
+        self.breached.len()
+    }
+
+    /// Checks if a password may be used for an account.
+    /// On failure, this returns a message that can be shown to the user.
+    pub fn check(&self, password: &str) -> Result<(), String> {
+        let length = password.chars().count();
+        if length == 0 {
+            return Err("The password must not be empty".to_string());
+        }
+        if length < self.min_length {
+            return Err(format!("The password must be at least {} characters long", self.min_length));
+        }
+        if length > self.max_length {
+            return Err(format!("The password must not be longer than {} characters", self.max_length));
+        }
+        if self.breached.contains(password) {
+            return Err("This password is known from data breaches. Please choose another one".to_string());
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone)]
+pub struct AuthBackend {
+    db: MySqlPool,
+    hasher: Argon2Hasher,
+    password_policy: Arc<PasswordPolicy>,
+}
 
 impl AuthBackend {
-    pub fn new(pool: MySqlPool) -> Self {
-        Self(pool)
+    pub fn new(db: MySqlPool, hasher: Argon2Hasher, password_policy: PasswordPolicy) -> Self {
+        Self { db, hasher, password_policy: Arc::new(password_policy) }
+    }
+
+    /// The requirements new passwords have to meet.
+    /// `create_user` and `change_password` don't check them, so callers can report violations.
+    pub fn password_policy(&self) -> &PasswordPolicy {
+        &self.password_policy
+    }
+
+    /// Creates a new user with the given name and password.
+    /// Returns `AppError::AlreadyExists` if the name is already taken.
+    /// The name should be checked with `validate_username` beforehand.
+    pub async fn create_user(&self, name: &str, password: String) -> Result<User, AppError> {
+        // Hashing is just as expensive as verifying, so keep it off the IO workers.
+        let hasher = self.hasher.clone();
+        let password_hash = task::spawn_blocking(move || hasher.hash(&password)).await??;
+
+        let result = query!(
+            "insert into user (name, password) values (?, ?)",
+            name,
+            password_hash
+        ).execute(&self.db).await.map_err(|err| match err {
+            sqlx::Error::Database(ref db_err) if db_err.is_unique_violation() => AppError::AlreadyExists,
+            err => err.into(),
+        })?;
+
+        let user = query_as!(
+            UnsafeUser,
+            "select user_id, name, creation_date, password from user where user.user_id = ?",
+            result.last_insert_id()
+        ).fetch_one(&self.db).await?;
+        Ok(user.into())
+    }
+
+    /// Replaces the password of a user and returns the updated user.
+    /// Since the session auth hash is derived from the password hash, this invalidates
+    /// every session of the user. Log the returned user in again to keep the current one.
+    pub async fn change_password(&self, user_id: i32, password: String) -> Result<User, AppError> {
+        let hasher = self.hasher.clone();
+        let password_hash = task::spawn_blocking(move || hasher.hash(&password)).await??;
+
+        query!(
+            "update user set password = ? where user_id = ?",
+            password_hash,
+            user_id
+        ).execute(&self.db).await?;
+
+        let user = query_as!(
+            UnsafeUser,
+            "select user_id, name, creation_date, password from user where user.user_id = ?",
+            user_id
+        ).fetch_one(&self.db).await?;
+        Ok(user.into())
+    }
+
+    /// Creates a new API token for a user and returns it.
+    /// Only the hash of the token is stored, so this is the only time the token is known.
+    pub async fn create_api_token(&self, user_id: i32, name: &str, scope: TokenScope) -> Result<String, AppError> {
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = format!(
+            "{}{}",
+            API_TOKEN_PREFIX,
+            base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes)
+        );
+
+        query!(
+            "insert into api_token (user_id, name, token_hash, scope) values (?, ?, ?, ?)",
+            user_id,
+            name,
+            hash_api_token(&token),
+            scope
+        ).execute(&self.db).await?;
+        Ok(token)
+    }
+
+    /// Looks up the user an API token belongs to along with the scope of the token.
+    /// Returns `None` for unknown or revoked tokens. Records the use of known tokens.
+    pub async fn authenticate_api_token(&self, token: &str) -> Result<Option<(User, TokenScope)>, AppError> {
+        if !token.starts_with(API_TOKEN_PREFIX) {
+            return Ok(None);
+        }
+        let token = query!(
+            "select token_id, user_id, scope as 'scope: TokenScope' from api_token where token_hash = ?",
+            hash_api_token(token)
+        ).fetch_optional(&self.db).await?;
+        let Some(token) = token else {
+            return Ok(None);
+        };
+
+        query!("update api_token set last_used = current_timestamp where token_id = ?", token.token_id)
+            .execute(&self.db).await?;
+        let user = self.get_user(&token.user_id).await?;
+        Ok(user.map(|user| (user, token.scope)))
+    }
+}
+
+/// Prefix of every API token, so leaked tokens are easy to recognize.
+const API_TOKEN_PREFIX: &str = "mydex_";
+
+/// The maximum length of an API token name. This is limited by the `api_token.name` column.
+pub const MAX_API_TOKEN_NAME_LENGTH: usize = 128;
+
+/// What a request authenticated with an API token may do.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum TokenScope {
+    /// Everything the user may do.
+    Full,
+    /// Reading everything and marking entries as collected or not collected.
+    ProgressWrite,
+    /// Only reading.
+    ReadOnly,
+}
+
+impl TokenScope {
+    pub fn name(&self) -> &'static str {
+        match self {
+            TokenScope::Full => "full",
+            TokenScope::ProgressWrite => "progress_write",
+            TokenScope::ReadOnly => "read_only",
+        }
+    }
+
+    /// Checks if a request to `path` with `method` is within the scope.
+    pub fn allows(&self, method: &Method, path: &str) -> bool {
+        let is_read = method == Method::GET || method == Method::HEAD;
+        match self {
+            TokenScope::Full => true,
+            TokenScope::ProgressWrite => is_read || is_progress_route(path),
+            TokenScope::ReadOnly => is_read,
+        }
     }
 }
 
+/// Checks if the route at `path` only changes the progress in a Pokédex.
+fn is_progress_route(path: &str) -> bool {
+    let segments: Vec<&str> = path.trim_start_matches('/').split('/').collect();
+    matches!(
+        segments.as_slice(),
+        ["user", _, "pokedex", _, "entry", ..]
+            | ["user", _, "pokedex", _, "entries"]
+            | ["api", "v1", "users", _, "pokedexes", _, "entries", ..]
+    )
+}
+
+/// Hashes an API token for storage and lookup.
+/// Tokens are random, so unlike passwords they don't need a slow, salted hash.
+fn hash_api_token(token: &str) -> String {
+    format!("{:x}", Sha256::digest(token.as_bytes()))
+}
+
+/// The maximum length of a username. This is limited by the `user.name` column.
+pub const MAX_USERNAME_LENGTH: usize = 64;
+
+/// Checks if a username may be used for an account.
+/// Usernames are part of URLs, so only a URL-safe set of characters is allowed.
+/// On failure, this returns a message that can be shown to the user.
+pub fn validate_username(name: &str) -> Result<(), &'static str> {
+    if name.is_empty() {
+        return Err("The username must not be empty");
+    }
+    if name.chars().count() > MAX_USERNAME_LENGTH {
+        return Err("The username must not be longer than 64 characters");
+    }
+    if !name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_') {
+        return Err("The username may only contain letters, digits, '-' and '_'");
+    }
+    Ok(())
+}
+
 #[async_trait]
 impl AuthnBackend for AuthBackend {
     type User = User;
@@ -118,26 +402,55 @@ impl AuthnBackend for AuthBackend {
     async fn authenticate(&self, creds: Self::Credentials) -> Result<Option<Self::User>, Self::Error> {
         let user = query_as!(
             UnsafeUser, 
-            "select * from user where user.name = ?", 
+            "select user_id, name, creation_date, password from user where user.name = ?", 
             creds.username
-        ).fetch_optional(&self.0).await?;
+        ).fetch_optional(&self.db).await?;
+        let Some(mut user) = user else {
+            return Ok(None);
+        };
 
-        let user = task::spawn_blocking(|| {
+        let hasher = self.hasher.clone();
+        let stored_hash = user.password.clone();
+        let verification = task::spawn_blocking(move || -> Result<Verification, AppError> {
             // Since the password verification may take some time, we offload it to 
             // a compute worker. This way we avoid blocking IO workers.
-            user
-                .filter(|user| verify_password(creds.password, &user.password).is_ok())
-                .map(|user| user.into())
-        }).await?;
-        Ok(user)
+            if verify_password(&creds.password, &stored_hash).is_err() {
+                return Ok(Verification::Rejected);
+            }
+            // The password is only known right now, so this is the time to replace an outdated hash.
+            if hasher.is_outdated(&stored_hash) {
+                return Ok(Verification::Rehashed(hasher.hash(&creds.password)?));
+            }
+            Ok(Verification::Accepted)
+        }).await??;
+
+        match verification {
+            Verification::Rejected => return Ok(None),
+            Verification::Accepted => {}
+            Verification::Rehashed(password_hash) => {
+                // The session auth hash is derived from the password hash, so this logs out
+                // the other sessions of the user once. The password must not have changed meanwhile.
+                let result = query!(
+                    "update user set password = ? where user_id = ? and password = ?",
+                    password_hash,
+                    user.user_id,
+                    user.password
+                ).execute(&self.db).await?;
+                if result.rows_affected() > 0 {
+                    info!("Rehashed the password of user {} with the current parameters.", user.name);
+                    user.password = password_hash;
+                }
+            }
+        }
+        Ok(Some(user.into()))
     }
 
     async fn get_user(&self, user_id: &UserId<Self>) -> Result<Option<Self::User>, Self::Error> {
         let user = query_as!(
             UnsafeUser, 
-            "select * from user where user.user_id = ?", 
+            "select user_id, name, creation_date, password from user where user.user_id = ?", 
             user_id
-        ).fetch_optional(&self.0).await?;
+        ).fetch_optional(&self.db).await?;
         Ok(user.map(|u| u.into()))
     }
 }
@@ -154,7 +467,7 @@ impl AuthzBackend for AuthBackend {
             where user_group.user_id = ?",
             user.user_id
         )
-            .fetch_all(&self.0)
+            .fetch_all(&self.db)
             .await?;
         Ok(HashSet::from_iter(res.into_iter()))
     }
@@ -162,10 +475,163 @@ impl AuthzBackend for AuthBackend {
 
 #[derive(Copy, Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
 #[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
 pub enum Permission {
     AddRole,
     RemoveRole,
     AddPokedexToOtherProfiles,
     RemovePokedexFromOtherProfiles,
+    EditPokedexProgressOfOtherProfiles,
+    ManagePokedexes,
+    ViewHiddenProfiles,
+    ManageLockouts,
+    ViewAuditLog,
 }
+
+impl Permission {
+    /// Every permission there is. Used to offer all of them in the administration UI.
+    pub const ALL: [Permission; 9] = [
+        Permission::AddRole,
+        Permission::RemoveRole,
+        Permission::AddPokedexToOtherProfiles,
+        Permission::RemovePokedexFromOtherProfiles,
+        Permission::EditPokedexProgressOfOtherProfiles,
+        Permission::ManagePokedexes,
+        Permission::ViewHiddenProfiles,
+        Permission::ManageLockouts,
+        Permission::ViewAuditLog,
+    ];
+
+    /// The name of the permission as it is stored in the database.
+    pub fn name(&self) -> &'static str {
+        match self {
+            Permission::AddRole => "add_role",
+            Permission::RemoveRole => "remove_role",
+            Permission::AddPokedexToOtherProfiles => "add_pokedex_to_other_profiles",
+            Permission::RemovePokedexFromOtherProfiles => "remove_pokedex_from_other_profiles",
+            Permission::EditPokedexProgressOfOtherProfiles => "edit_pokedex_progress_of_other_profiles",
+            Permission::ManagePokedexes => "manage_pokedexes",
+            Permission::ViewHiddenProfiles => "view_hidden_profiles",
+            Permission::ManageLockouts => "manage_lockouts",
+            Permission::ViewAuditLog => "view_audit_log",
+        }
+    }
+}
+
+/// The group the default 'admin' user is part of.
+/// There always has to be at least one user in this group.
+pub const ADMIN_GROUP: &str = "admin";
 pub type AuthSession = axum_login::AuthSession<AuthBackend>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WRITES: [Method; 4] = [Method::PUT, Method::POST, Method::DELETE, Method::PATCH];
+
+    #[test]
+    fn progress_routes() {
+        let routes = [
+            "/user/ash/pokedex/national/entry/25",
+            "/user/ash/pokedex/national/entry/25/linked",
+            "/user/ash/pokedex/national/entries",
+            "/api/v1/users/ash/pokedexes/national/entries",
+            "/api/v1/users/ash/pokedexes/national/entries/25",
+            "/api/v1/users/ash/pokedexes/national/entries/25/linked",
+        ];
+        for path in routes {
+            assert!(is_progress_route(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn other_routes() {
+        let routes = [
+            "/user/ash",
+            "/user/ash/pokedex/national",
+            "/user/ash/pokedex/national/entries/25",
+            "/user/ash/pokedex/national/import",
+            "/api/v1/users/ash",
+            "/api/v1/users/ash/pokedexes/national",
+            "/api/v1/pokedexes/national/entries",
+            "/settings/password",
+            "/admin/api/groups/admin/users/ash",
+        ];
+        for path in routes {
+            assert!(!is_progress_route(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn full_scope_allows_everything() {
+        for method in [Method::GET, Method::HEAD].into_iter().chain(WRITES) {
+            assert!(TokenScope::Full.allows(&method, "/settings/password"));
+            assert!(TokenScope::Full.allows(&method, "/api/v1/users/ash/pokedexes/national/entries"));
+        }
+    }
+
+    #[test]
+    fn progress_write_scope_only_writes_progress() {
+        let scope = TokenScope::ProgressWrite;
+        assert!(scope.allows(&Method::GET, "/api/v1/users/ash"));
+        assert!(scope.allows(&Method::HEAD, "/user/ash"));
+        for method in WRITES {
+            assert!(scope.allows(&method, "/api/v1/users/ash/pokedexes/national/entries/25"));
+            assert!(scope.allows(&method, "/user/ash/pokedex/national/entry/25/linked"));
+            assert!(!scope.allows(&method, "/api/v1/users/ash/pokedexes/national"));
+            assert!(!scope.allows(&method, "/settings/password"));
+        }
+    }
+
+    #[test]
+    fn read_only_scope_only_reads() {
+        let scope = TokenScope::ReadOnly;
+        assert!(scope.allows(&Method::GET, "/api/v1/users/ash/pokedexes/national/entries"));
+        assert!(scope.allows(&Method::HEAD, "/api/v1/users/ash"));
+        for method in WRITES {
+            assert!(!scope.allows(&method, "/api/v1/users/ash/pokedexes/national/entries/25"));
+            assert!(!scope.allows(&method, "/user/ash/pokedex/national/entry/25/linked"));
+        }
+    }
+
+    #[test]
+    fn passwords_within_the_limits_are_accepted() {
+        let policy = PasswordPolicy::new(4, 16);
+        assert!(policy.check("hunt").is_ok());
+        assert!(policy.check("correct horse 42").is_ok());
+    }
+
+    #[test]
+    fn short_passwords_are_rejected() {
+        let policy = PasswordPolicy::new(4, 16);
+        assert!(policy.check("").is_err());
+        assert!(policy.check("abc").is_err());
+        // The length is counted in characters, not bytes.
+        assert!(policy.check("äöü").is_err());
+    }
+
+    #[test]
+    fn long_passwords_are_rejected() {
+        let policy = PasswordPolicy::new(4, 16);
+        assert!(policy.check(&"a".repeat(16)).is_ok());
+        assert!(policy.check(&"a".repeat(17)).is_err());
+        assert!(policy.check(&"ä".repeat(16)).is_ok());
+    }
+
+    #[test]
+    fn breached_passwords_are_rejected() {
+        let path = std::env::temp_dir().join(format!("mydex-breached-{}.txt", std::process::id()));
+        std::fs::write(&path, "123456\r\npassword\n\nqwerty\n").unwrap();
+        let policy = PasswordPolicy::new(4, 16).with_breached_list(&path);
+        std::fs::remove_file(&path).unwrap();
+        let policy = policy.unwrap();
+
+        assert_eq!(policy.num_breached(), 3);
+        assert!(policy.check("123456").is_err());
+        assert!(policy.check("password").is_err());
+        assert!(policy.check("qwerty").is_err());
+        // Only exact matches are rejected.
+        assert!(policy.check("Password").is_ok());
+        assert!(policy.check("qwerty1").is_ok());
+    }
+}
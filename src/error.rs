@@ -2,6 +2,7 @@ use std::backtrace::Backtrace;
 use askama::Template;
 use axum::http::StatusCode;
 use axum::response::{Html, IntoResponse, Response};
+use axum::Extension;
 use thiserror::Error;
 use tokio::task::JoinError;
 use tracing::error;
@@ -25,14 +26,29 @@ pub enum AppError {
     },
     #[error(transparent)]
     JoinError(#[from] JoinError),
+    #[error("Failed to parse JSON data: {0}.")]
+    Json(#[from] serde_json::Error),
+    #[error(transparent)]
+    Internal(#[from] anyhow::Error),
     #[error("Not found.")]  
     NotFound,
     #[error("Unauthorized.")]
     Unauthorized,
     #[error("Already exists.")]
     AlreadyExists,
+    #[error("Bad request.")]
+    BadRequest,
+    #[error("Conflict.")]
+    Conflict,
+    #[error("Missing or invalid CSRF token or origin.")]
+    Csrf,
 }
 
+/// Attached to the responses of errors, so layers can present the error differently.
+/// This holds the `user_facing_error` of the error.
+#[derive(Copy, Clone, Debug)]
+pub struct ErrorMessage(pub &'static str);
+
 impl AppError {
 
     /// The HTTP status code appropriate for this error.
@@ -41,9 +57,14 @@ impl AppError {
             AppError::TemplateRender(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::Sqlx { .. } => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::JoinError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Json(_) => StatusCode::INTERNAL_SERVER_ERROR,
+            AppError::Internal(_) => StatusCode::INTERNAL_SERVER_ERROR,
             AppError::NotFound => StatusCode::NOT_FOUND,  
             AppError::Unauthorized => StatusCode::UNAUTHORIZED,
             AppError::AlreadyExists => StatusCode::BAD_REQUEST,
+            AppError::BadRequest => StatusCode::BAD_REQUEST,
+            AppError::Conflict => StatusCode::CONFLICT,
+            AppError::Csrf => StatusCode::FORBIDDEN,
         }
     }
 
@@ -55,9 +76,14 @@ impl AppError {
             AppError::TemplateRender(_) => "Internal Server Error",
             AppError::Sqlx { .. } => "Internal Server Error",
             AppError::JoinError(_) => "Internal Server Error",
+            AppError::Json(_) => "Internal Server Error",
+            AppError::Internal(_) => "Internal Server Error",
             AppError::NotFound => "Not Found",
             AppError::Unauthorized => "Unauthorized",
             AppError::AlreadyExists => "The resource already exists",
+            AppError::BadRequest => "Bad Request",
+            AppError::Conflict => "The request conflicts with the current state of the resource",
+            AppError::Csrf => "The request could not be verified. Reload the page and try again",
         }
     }
 }
@@ -67,12 +93,13 @@ impl IntoResponse for AppError {
     fn into_response(self) -> Response {
         // Render the error page and return it along with a status to the user.
         let status_code = self.status_code();
+        let message = self.user_facing_error();
         let error_page = ErrorPageTemplate {
             status_code: status_code.as_u16(),
-            message: self.user_facing_error(),
+            message,
         }.render();
         match error_page {
-            Ok(error_page) => (status_code, Html(error_page)).into_response(),
+            Ok(error_page) => (status_code, Extension(ErrorMessage(message)), Html(error_page)).into_response(),
             Err(err) => {
                 // Something went wrong with rendering the error template too.
                 // Fall back to the static fallback page string.
@@ -2,8 +2,10 @@
 
 use std::process::exit;
 use envconfig::Envconfig;
+use sqlx::MySqlPool;
 use tracing::error;
 use tracing_subscriber::EnvFilter;
+use crate::pokedex::{PokedexSynchronizer, SyncMode};
 use crate::web::App;
 
 mod web;
@@ -19,14 +21,67 @@ pub struct Config {
     pub bind_addr: String,
     #[envconfig(from = "COOKIE_KEY")]
     pub cookie_key: Option<String>,
+    #[envconfig(from = "COOKIE_NAME", default = "id")]
+    pub cookie_name: String,
+    /// Only send the session cookie over HTTPS.
+    #[envconfig(from = "COOKIE_SECURE", default = "false")]
+    pub cookie_secure: bool,
+    /// One of `strict`, `lax` or `none`.
+    #[envconfig(from = "COOKIE_SAME_SITE", default = "strict")]
+    pub cookie_same_site: String,
+    /// Without a domain, the cookie is only sent to the host that set it.
+    #[envconfig(from = "COOKIE_DOMAIN")]
+    pub cookie_domain: Option<String>,
+    #[envconfig(from = "COOKIE_PATH", default = "/")]
+    pub cookie_path: String,
+    /// Seconds without a request until a session ends.
+    #[envconfig(from = "SESSION_INACTIVITY_LIFETIME", default = "86400")]
+    pub session_inactivity_lifetime: u32,
+    /// Seconds until a login ends, no matter how active the user is.
+    #[envconfig(from = "SESSION_ABSOLUTE_LIFETIME", default = "604800")]
+    pub session_absolute_lifetime: u32,
+    /// Seconds until a login with "remember me" ends.
+    #[envconfig(from = "SESSION_REMEMBER_LIFETIME", default = "2592000")]
+    pub session_remember_lifetime: u32,
+    /// Memory used to hash a password, in KiB.
+    /// Stored hashes with other argon2 parameters are replaced when their users log in.
+    #[envconfig(from = "ARGON2_MEMORY_COST", default = "19456")]
+    pub argon2_memory_cost: u32,
+    /// Passes over the memory to hash a password.
+    #[envconfig(from = "ARGON2_TIME_COST", default = "2")]
+    pub argon2_time_cost: u32,
+    #[envconfig(from = "ARGON2_PARALLELISM", default = "1")]
+    pub argon2_parallelism: u32,
+    #[envconfig(from = "PASSWORD_MIN_LENGTH", default = "8")]
+    pub password_min_length: usize,
+    #[envconfig(from = "PASSWORD_MAX_LENGTH", default = "256")]
+    pub password_max_length: usize,
+    /// A file of breached passwords with one password per line. They can't be chosen as passwords.
+    #[envconfig(from = "PASSWORD_BREACHED_LIST")]
+    pub password_breached_list: Option<String>,
 }
 
+/// Command line flag to print what a definition sync would change instead of starting the server.
+const SYNC_DRY_RUN_FLAG: &str = "--sync-dry-run";
+
 async fn run() -> Result<(), anyhow::Error> {
     let config = Config::init_from_env()?;
+    if std::env::args().any(|arg| arg == SYNC_DRY_RUN_FLAG) {
+        return sync_dry_run(config).await;
+    }
     let app = App::new(config).await?;
     app.serve().await
 }
 
+/// Reports how the pokedex definitions on disk differ from the database without changing anything.
+/// The database has to be migrated already, because this does not run migrations.
+async fn sync_dry_run(config: Config) -> Result<(), anyhow::Error> {
+    let pool = MySqlPool::connect(config.database_url.as_str()).await?;
+    let report = PokedexSynchronizer::new(pool).sync(SyncMode::DryRun).await?;
+    print!("{}", report);
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() {
 
@@ -0,0 +1,476 @@
+mod sync;
+mod watcher;
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::path::Path;
+use anyhow::{bail, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use utoipa::ToSchema;
+
+pub use sync::{PokedexSynchronizer, SyncMode};
+pub use watcher::watch_definitions;
+
+/// The gender of a Pokémon, for entries that differ between genders.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, ToSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Gender {
+    Male,
+    Female,
+}
+
+/// A single entry of a compiled Pokédex definition.
+#[derive(Clone, Debug, Serialize, Deserialize, ToSchema)]
+pub struct PokedexEntry {
+    /// Identifies the entry within its Pokédex. Progress is stored by this ID.
+    pub id: i32,
+    /// Optional stable identifier of the entry.
+    /// Unlike the ID, the key stays the same when entries are reordered,
+    /// so progress can follow the entry to its new ID.
+    #[serde(default)]
+    pub key: Option<String>,
+    /// Identifies the species of the entry across Pokédexes,
+    /// so progress in one Pokédex can be linked to the same species in another.
+    #[serde(default)]
+    pub species: Option<String>,
+    pub name: String,
+    #[serde(default)]
+    pub form: Option<String>,
+    /// Index of the entry's image in the spritesheet of the Pokédex.
+    pub sprite: i32,
+    #[serde(default)]
+    pub shiny: bool,
+    #[serde(default)]
+    pub gmax: bool,
+    #[serde(default)]
+    pub technical: bool,
+    #[serde(default)]
+    pub gender: Option<Gender>,
+}
+
+impl PokedexEntry {
+
+    /// Checks if the entry shows the plain version of its species:
+    /// not shiny, without a form, Gigantamax or gender difference.
+    pub fn is_base_form(&self) -> bool {
+        !self.shiny && self.form.is_none() && !self.gmax && self.gender.is_none()
+    }
+
+    /// Checks if two entries describe the same Pokémon, ignoring their IDs and sprites.
+    fn is_same_pokemon(&self, other: &PokedexEntry) -> bool {
+        self.name == other.name
+            && self.form == other.form
+            && self.shiny == other.shiny
+            && self.gmax == other.gmax
+            && self.gender == other.gender
+    }
+}
+
+/// The entries of a Pokédex in display order.
+/// Empty slots are `None`. They only take up space in the layout.
+pub type PokedexEntries = Vec<Option<PokedexEntry>>;
+
+/// A compiled Pokédex definition as written by `scripts/compile_data.py`.
+#[derive(Debug, Serialize, Deserialize)]
+struct Pokedex {
+    id: String,
+    name: String,
+    description: String,
+    num_entries: i32,
+    thumbnail_url: String,
+    spritesheet_url: String,
+    commit_hash: String,
+    entries: PokedexEntries,
+    /// Maps entry IDs of earlier revisions to their new IDs.
+    /// Only needed for entries without a key that moved to a different ID.
+    #[serde(default)]
+    remap: BTreeMap<i32, i32>,
+}
+
+/// The layout of a definition file before the entries are parsed.
+/// Entries are parsed one by one, so errors can point to the broken entry.
+#[derive(Debug, Deserialize)]
+struct PokedexFile {
+    id: String,
+    name: String,
+    description: String,
+    num_entries: i32,
+    thumbnail_url: String,
+    spritesheet_url: String,
+    commit_hash: String,
+    entries: Vec<serde_json::Value>,
+    #[serde(default)]
+    remap: BTreeMap<i32, i32>,
+}
+
+impl Pokedex {
+
+    /// Parses and validates a compiled definition.
+    /// `path` is only used to point to the file in error messages.
+    fn parse(path: &Path, content: &str) -> anyhow::Result<Self> {
+        let file: PokedexFile = serde_json::from_str(content)
+            .with_context(|| format!("Failed to parse pokedex definition {}", path.display()))?;
+
+        let entries = file.entries
+            .into_iter()
+            .enumerate()
+            .map(|(index, entry)| {
+                serde_json::from_value(entry).with_context(|| format!(
+                    "Failed to parse entry {} of pokedex definition {}", index, path.display()
+                ))
+            })
+            .collect::<anyhow::Result<PokedexEntries>>()?;
+
+        let pokedex = Pokedex {
+            id: file.id,
+            name: file.name,
+            description: file.description,
+            num_entries: file.num_entries,
+            thumbnail_url: file.thumbnail_url,
+            spritesheet_url: file.spritesheet_url,
+            commit_hash: file.commit_hash,
+            entries,
+            remap: file.remap,
+        };
+        pokedex.validate(path)?;
+        Ok(pokedex)
+    }
+
+    /// Hashes everything about the definition that ends up on a page, and the remap table,
+    /// so a commit that only fixes the remap table still gets synchronized.
+    /// The commit hash is left out, since it changes with commits that don't touch the content.
+    fn content_hash(&self) -> anyhow::Result<String> {
+        let content = serde_json::to_vec(&(
+            &self.id,
+            &self.name,
+            &self.description,
+            self.num_entries,
+            &self.thumbnail_url,
+            &self.spritesheet_url,
+            &self.entries,
+            &self.remap,
+        ))?;
+        Ok(format!("{:x}", Sha256::digest(content)))
+    }
+
+    /// Makes sure the entries are consistent with each other and the rest of the definition.
+    fn validate(&self, path: &Path) -> anyhow::Result<()> {
+        let mut ids = HashSet::new();
+        let mut keys = HashSet::new();
+        for (index, entry) in self.entries.iter().enumerate() {
+            let Some(entry) = entry else {
+                continue;
+            };
+            if !ids.insert(entry.id) {
+                bail!("Entry {} of pokedex definition {} has the duplicate ID {}", index, path.display(), entry.id);
+            }
+            if let Some(key) = &entry.key {
+                if !keys.insert(key) {
+                    bail!("Entry {} of pokedex definition {} has the duplicate key {}", index, path.display(), key);
+                }
+            }
+            if entry.sprite < 0 {
+                bail!("Entry {} of pokedex definition {} has the negative sprite index {}", index, path.display(), entry.sprite);
+            }
+        }
+        if ids.len() != self.num_entries as usize {
+            bail!(
+                "Pokedex definition {} declares {} entries, but has {}",
+                path.display(), self.num_entries, ids.len()
+            );
+        }
+        for (old_id, new_id) in &self.remap {
+            if !ids.contains(new_id) {
+                bail!("Pokedex definition {} remaps entry {} to the unknown entry {}", path.display(), old_id, new_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Figures out which entry of this definition each entry of the previous revision has become.
+    /// Entries are matched by their key first, then by the remap table, and finally by their ID.
+    fn map_entries_from(&self, previous: &PokedexEntries) -> EntryMapping {
+        let by_key: HashMap<&str, i32> = self.entries
+            .iter()
+            .flatten()
+            .filter_map(|entry| entry.key.as_deref().map(|key| (key, entry.id)))
+            .collect();
+        let by_id: HashMap<i32, &PokedexEntry> = self.entries
+            .iter()
+            .flatten()
+            .map(|entry| (entry.id, entry))
+            .collect();
+
+        let mut mapping = EntryMapping::default();
+        for old in previous.iter().flatten() {
+            let new_id = old.key
+                .as_deref()
+                .and_then(|key| by_key.get(key).copied())
+                .or_else(|| {
+                    // The remap table stays in the definition after it has been applied once.
+                    // If the entry under the old ID is still the same Pokémon, the table is
+                    // left over from an earlier revision and must not be applied again.
+                    let is_unchanged = by_id.get(&old.id).is_some_and(|new| new.is_same_pokemon(old));
+                    if is_unchanged { None } else { self.remap.get(&old.id).copied() }
+                })
+                .or_else(|| {
+                    // Two entries with different keys are never the same, even with the same ID.
+                    by_id.get(&old.id)
+                        .filter(|new| old.key.is_none() || new.key.is_none())
+                        .map(|new| new.id)
+                });
+            match new_id {
+                Some(new_id) => { mapping.ids.insert(old.id, new_id); },
+                None => mapping.orphaned.push(old.id),
+            }
+        }
+        mapping.orphaned.sort();
+        mapping
+    }
+}
+
+/// Describes how the progress on the entries of a previous revision carries over to a new one.
+#[derive(Debug, Default)]
+struct EntryMapping {
+    /// Entry IDs of the previous revision mapped to their IDs in the new revision.
+    ids: HashMap<i32, i32>,
+    /// Entry IDs of the previous revision that don't exist anymore.
+    orphaned: Vec<i32>,
+}
+
+impl EntryMapping {
+
+    /// Whether all progress stays exactly where it is.
+    fn is_identity(&self) -> bool {
+        self.orphaned.is_empty() && self.ids.iter().all(|(old_id, new_id)| old_id == new_id)
+    }
+}
+
+/// Parses the entries of a Pokédex as they are stored in the `pokedex.entries` column.
+pub fn parse_entries(entries: serde_json::Value) -> Result<PokedexEntries, serde_json::Error> {
+    serde_json::from_value(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> anyhow::Result<Pokedex> {
+        Pokedex::parse(Path::new("test.json"), content)
+    }
+
+    #[test]
+    fn valid_definitions_are_accepted() {
+        let pokedex = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 2,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [
+                {"id": 1, "name": "Bulbasaur", "sprite": 0},
+                null,
+                {"id": 2, "name": "Ivysaur", "sprite": 1, "shiny": true, "gender": "female"}
+            ]
+        }"#).unwrap();
+        assert_eq!(pokedex.entries.len(), 3);
+        assert!(pokedex.entries[1].is_none());
+        let ivysaur = pokedex.entries[2].as_ref().unwrap();
+        assert!(ivysaur.shiny && !ivysaur.gmax);
+        assert_eq!(ivysaur.gender, Some(Gender::Female));
+    }
+
+    #[test]
+    fn broken_entries_are_rejected() {
+        let result = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 1,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "sprite": 0}]
+        }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn duplicate_ids_are_rejected() {
+        let result = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 2,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "name": "Bulbasaur", "sprite": 0}, {"id": 1, "name": "Ivysaur", "sprite": 1}]
+        }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn negative_sprites_are_rejected() {
+        let result = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 1,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "name": "Bulbasaur", "sprite": -1}]
+        }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn wrong_entry_counts_are_rejected() {
+        let result = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 3,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "name": "Bulbasaur", "sprite": 0}, null, {"id": 2, "name": "Ivysaur", "sprite": 1}]
+        }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn duplicate_keys_are_rejected() {
+        let result = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 2,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [
+                {"id": 1, "key": "bulbasaur", "name": "Bulbasaur", "sprite": 0},
+                {"id": 2, "key": "bulbasaur", "name": "Ivysaur", "sprite": 1}
+            ]
+        }"#);
+        assert!(result.is_err());
+    }
+
+    #[test]
+    fn remaps_to_unknown_entries_are_rejected() {
+        let result = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 1,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "name": "Bulbasaur", "sprite": 0}],
+            "remap": {"2": 3}
+        }"#);
+        assert!(result.is_err());
+    }
+
+    /// Maps the entries of the `previous` definition to the `current` one.
+    fn mapping(previous: &str, current: &str) -> EntryMapping {
+        let previous = parse(previous).unwrap();
+        parse(current).unwrap().map_entries_from(&previous.entries)
+    }
+
+    #[test]
+    fn unchanged_entries_keep_their_ids() {
+        let definition = r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 2,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "name": "Bulbasaur", "sprite": 0}, {"id": 2, "key": "ivysaur", "name": "Ivysaur", "sprite": 1}]
+        }"#;
+        assert!(mapping(definition, definition).is_identity());
+    }
+
+    #[test]
+    fn keys_follow_reordered_entries() {
+        let mapping = mapping(
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 2,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [
+                    {"id": 1, "key": "bulbasaur", "name": "Bulbasaur", "sprite": 0},
+                    {"id": 2, "key": "ivysaur", "name": "Ivysaur", "sprite": 1}
+                ]
+            }"#,
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 2,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [
+                    {"id": 1, "key": "ivysaur", "name": "Ivysaur", "sprite": 1},
+                    {"id": 2, "key": "bulbasaur", "name": "Bulbasaur", "sprite": 0}
+                ]
+            }"#,
+        );
+        assert_eq!(mapping.ids, HashMap::from([(1, 2), (2, 1)]));
+        assert!(mapping.orphaned.is_empty());
+    }
+
+    #[test]
+    fn remaps_move_entries_without_keys() {
+        let mapping = mapping(
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 2,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [{"id": 1, "name": "Pikachu", "sprite": 0}, {"id": 2, "name": "Raichu", "sprite": 1}]
+            }"#,
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 3,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [
+                    {"id": 1, "name": "Pichu", "sprite": 2},
+                    {"id": 2, "name": "Raichu", "sprite": 1},
+                    {"id": 3, "name": "Pikachu", "sprite": 0}
+                ],
+                "remap": {"1": 3}
+            }"#,
+        );
+        assert_eq!(mapping.ids, HashMap::from([(1, 3), (2, 2)]));
+        assert!(mapping.orphaned.is_empty());
+    }
+
+    #[test]
+    fn applied_remaps_are_not_applied_again() {
+        let definition = r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 3,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [
+                {"id": 1, "name": "Pichu", "sprite": 2},
+                {"id": 2, "name": "Raichu", "sprite": 1},
+                {"id": 3, "name": "Pikachu", "sprite": 0}
+            ],
+            "remap": {"1": 3}
+        }"#;
+        assert!(mapping(definition, definition).is_identity());
+    }
+
+    #[test]
+    fn removed_entries_are_orphaned() {
+        let mapping = mapping(
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 3,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [
+                    {"id": 1, "name": "Bulbasaur", "sprite": 0},
+                    {"id": 2, "name": "Ivysaur", "sprite": 1},
+                    {"id": 3, "name": "Venusaur", "sprite": 2}
+                ]
+            }"#,
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 2,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [{"id": 1, "name": "Bulbasaur", "sprite": 0}, {"id": 2, "name": "Ivysaur", "sprite": 1}]
+            }"#,
+        );
+        assert_eq!(mapping.orphaned, vec![3]);
+    }
+
+    #[test]
+    fn entries_with_different_keys_are_never_the_same() {
+        let mapping = mapping(
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 1,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [{"id": 1, "key": "bulbasaur", "name": "Bulbasaur", "sprite": 0}]
+            }"#,
+            r#"{
+                "id": "test", "name": "Test", "description": "", "num_entries": 1,
+                "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+                "entries": [{"id": 1, "key": "ivysaur", "name": "Ivysaur", "sprite": 0}]
+            }"#,
+        );
+        assert!(mapping.ids.is_empty());
+        assert_eq!(mapping.orphaned, vec![1]);
+    }
+
+    #[test]
+    fn remaps_change_the_content_hash() {
+        let without_remap = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 1,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "name": "Bulbasaur", "sprite": 0}]
+        }"#).unwrap();
+        let with_remap = parse(r#"{
+            "id": "test", "name": "Test", "description": "", "num_entries": 1,
+            "thumbnail_url": "", "spritesheet_url": "", "commit_hash": "",
+            "entries": [{"id": 1, "name": "Bulbasaur", "sprite": 0}],
+            "remap": {"2": 1}
+        }"#).unwrap();
+        assert_ne!(without_remap.content_hash().unwrap(), with_remap.content_hash().unwrap());
+    }
+}
@@ -0,0 +1,432 @@
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+use std::sync::Arc;
+use serde::Serialize;
+use sqlx::{query, MySqlConnection, MySqlPool};
+use tokio::fs;
+use tokio::sync::Mutex;
+use tracing::{debug, error, info, warn};
+use super::{parse_entries, Pokedex, PokedexEntries};
+
+/// The directory the compiled pokedex definitions are loaded from.
+pub const DEFINITION_DIRECTORY: &str = "data/pokedexes";
+
+/// Whether a synchronization changes the database or only reports what it would change.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum SyncMode {
+    Apply,
+    DryRun,
+}
+
+/// What happens to a single definition file during a synchronization.
+#[derive(Debug, Serialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum FileStatus {
+    Inserted {
+        pokedex_id: String,
+        revision: i32,
+        num_entries: i32,
+    },
+    Updated {
+        pokedex_id: String,
+        revision: i32,
+        previous_num_entries: i32,
+        num_entries: i32,
+    },
+    Skipped {
+        pokedex_id: String,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+#[derive(Debug, Serialize)]
+pub struct FileReport {
+    pub file: String,
+    #[serde(flatten)]
+    pub status: FileStatus,
+}
+
+/// The outcome of a synchronization for every definition file.
+#[derive(Debug, Serialize)]
+pub struct SyncReport {
+    /// Whether the changes were written to the database.
+    /// This is false for dry runs and for synchronizations with broken definitions.
+    pub applied: bool,
+    pub files: Vec<FileReport>,
+    /// Pokedexes that are retired because their definition file is gone.
+    pub retired: Vec<String>,
+    /// Retired pokedexes whose definition file is back.
+    pub restored: Vec<String>,
+}
+
+impl SyncReport {
+
+    fn has_failures(&self) -> bool {
+        self.files.iter().any(|file| matches!(file.status, FileStatus::Failed { .. }))
+    }
+
+    /// Logs the outcome of a synchronization.
+    pub fn log(&self) {
+        if self.has_failures() {
+            error!("Pokedex definitions were not applied, because some of them are broken:\n{}", self);
+        } else if self.applied {
+            info!("Pokedex definitions were applied:\n{}", self);
+        } else {
+            info!("Pokedex definitions would be applied like this:\n{}", self);
+        }
+    }
+}
+
+impl Display for SyncReport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        for file in &self.files {
+            match &file.status {
+                FileStatus::Inserted { pokedex_id, revision, num_entries } => writeln!(
+                    f, "inserted {} from {}: revision {}, {} entries",
+                    pokedex_id, file.file, revision, num_entries
+                )?,
+                FileStatus::Updated { pokedex_id, revision, previous_num_entries, num_entries } => writeln!(
+                    f, "updated  {} from {}: revision {}, {} -> {} entries ({:+})",
+                    pokedex_id, file.file, revision, previous_num_entries, num_entries,
+                    num_entries - previous_num_entries
+                )?,
+                FileStatus::Skipped { pokedex_id } => writeln!(
+                    f, "skipped  {} from {}: already up-to-date", pokedex_id, file.file
+                )?,
+                FileStatus::Failed { error } => writeln!(f, "failed   {}: {}", file.file, error)?,
+            }
+        }
+        for pokedex_id in &self.retired {
+            writeln!(f, "retired  {}: definition was removed", pokedex_id)?;
+        }
+        for pokedex_id in &self.restored {
+            writeln!(f, "restored {}: definition is back", pokedex_id)?;
+        }
+        Ok(())
+    }
+}
+
+/// Synchronizes the pokedex definitions on disk with the database.
+/// Synchronizations may be triggered from several places at once,
+/// so this makes sure only one of them runs at a time.
+#[derive(Clone)]
+pub struct PokedexSynchronizer {
+    db: MySqlPool,
+    lock: Arc<Mutex<()>>,
+}
+
+impl PokedexSynchronizer {
+    pub fn new(db: MySqlPool) -> Self {
+        Self {
+            db,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Load the pokedex definitions and add them to our database.
+    /// All definitions are applied in a single transaction. If any of them is broken,
+    /// none of them are applied, and the broken ones are reported as failed.
+    /// In a dry run, the transaction is always rolled back.
+    pub async fn sync(&self, mode: SyncMode) -> anyhow::Result<SyncReport> {
+        let _guard = self.lock.lock().await;
+        let mut report = SyncReport {
+            applied: false,
+            files: vec![],
+            retired: vec![],
+            restored: vec![],
+        };
+
+        // Load the pokedex definitions from the file system
+        if fs::try_exists(DEFINITION_DIRECTORY).await.ok() != Some(true) {
+            warn!("No pokedex definitions found.");
+            return Ok(report)
+        }
+        let mut definitions = vec![];
+        let mut definition_paths = fs::read_dir(DEFINITION_DIRECTORY).await?;
+        while let Some(path) = definition_paths.next_entry().await? {
+            let is_json_file = match path.file_type().await {
+                Ok(t) => t.is_file() && path.file_name().to_string_lossy().ends_with(".json"),
+                Err(_) => false,
+            };
+            if !is_json_file {
+                continue;
+            }
+            let path = path.path();
+            let file = path.file_name().unwrap_or_default().to_string_lossy().into_owned();
+            match load_pokedex(&path).await {
+                Ok(definition) => definitions.push((file, definition)),
+                Err(err) => report.files.push(FileReport {
+                    file,
+                    status: FileStatus::Failed { error: format!("{:#}", err) },
+                }),
+            }
+        }
+
+        // Each pokedex may only be defined once.
+        let mut ids = HashSet::new();
+        let mut unique_definitions = vec![];
+        for (file, definition) in definitions {
+            if ids.insert(definition.id.clone()) {
+                unique_definitions.push((file, definition));
+            } else {
+                let error = format!("Pokedex {} is defined by more than one file", definition.id);
+                report.files.push(FileReport { file, status: FileStatus::Failed { error } });
+            }
+        }
+
+        // Apply everything in one transaction, so the database is never left half-updated.
+        let mut tx = self.db.begin().await?;
+        for (file, definition) in unique_definitions {
+            let status = update_pokedex(&mut *tx, definition).await?;
+            report.files.push(FileReport { file, status });
+        }
+
+        // We can't tell which pokedex a broken file belongs to.
+        // Retiring pokedexes now could retire the one that is only broken.
+        if !report.has_failures() {
+            update_retired_pokedexes(&mut *tx, &mut report).await?;
+        }
+
+        if mode == SyncMode::Apply && !report.has_failures() {
+            tx.commit().await?;
+            report.applied = true;
+            log_pokedex_revisions(&self.db).await?;
+        } else {
+            tx.rollback().await?;
+        }
+        Ok(report)
+    }
+}
+
+/// Retires every pokedex that has no definition file anymore and restores
+/// retired pokedexes that have one again.
+async fn update_retired_pokedexes(db: &mut MySqlConnection, report: &mut SyncReport) -> anyhow::Result<()> {
+    let on_disk: HashSet<&str> = report.files
+        .iter()
+        .filter_map(|file| match &file.status {
+            FileStatus::Inserted { pokedex_id, .. } => Some(pokedex_id.as_str()),
+            FileStatus::Updated { pokedex_id, .. } => Some(pokedex_id.as_str()),
+            FileStatus::Skipped { pokedex_id } => Some(pokedex_id.as_str()),
+            FileStatus::Failed { .. } => None,
+        })
+        .collect();
+
+    let mut retired = vec![];
+    let mut restored = vec![];
+    for pokedex in query!("select id, retired from pokedex").fetch_all(&mut *db).await? {
+        let is_on_disk = on_disk.contains(pokedex.id.as_str());
+        if pokedex.retired == is_on_disk {
+            query!("update pokedex set retired = ? where id = ?", !is_on_disk, pokedex.id)
+                .execute(&mut *db).await?;
+            if is_on_disk { restored.push(pokedex.id) } else { retired.push(pokedex.id) }
+        }
+    }
+    report.retired = retired;
+    report.restored = restored;
+    Ok(())
+}
+
+/// Loads and validates a single definition file.
+async fn load_pokedex(path: &Path) -> anyhow::Result<Pokedex> {
+    let content = fs::read_to_string(path).await?;
+    Pokedex::parse(path, content.as_str())
+}
+
+/// Updates the pokedex description and entries in the database.
+/// Every change to the definition is recorded as a new revision.
+async fn update_pokedex(db: &mut MySqlConnection, pokedex: Pokedex) -> anyhow::Result<FileStatus> {
+
+    // Figure out if the database is actually outdated.
+    let content_hash = pokedex.content_hash()?;
+    let existing = query!(
+        "select revision, content_hash, num_entries, entries from pokedex where id = ?",
+        pokedex.id
+    ).fetch_optional(&mut *db).await?;
+    let (revision, previous) = match existing {
+        Some(existing) if existing.content_hash == content_hash => {
+            debug!("Skipping pokedex update for '{}': Already up-to-date.", pokedex.name);
+            return Ok(FileStatus::Skipped { pokedex_id: pokedex.id });
+        }
+        Some(existing) => (
+            existing.revision + 1,
+            Some((existing.num_entries, parse_entries(existing.entries)?)),
+        ),
+        None => (1, None),
+    };
+
+    // Database is outdated. Update data or insert.
+    debug!("Updating pokedex {} to revision {}.", pokedex.name, revision);
+    update_pokedex_table(&mut *db, &pokedex, revision, &content_hash).await?;
+    let orphaned_entries = match &previous {
+        Some((_, previous_entries)) => migrate_progress(&mut *db, &pokedex, previous_entries, revision).await?,
+        None => vec![],
+    };
+    let orphaned_entries = if orphaned_entries.is_empty() {
+        None
+    } else {
+        Some(serde_json::to_value(&orphaned_entries)?)
+    };
+    query!(
+        "
+        insert into pokedex_revision (pokedex_id, revision, content_hash, commit_hash, orphaned_entries)
+        values (?, ?, ?, ?, ?)
+        ",
+        pokedex.id,
+        revision,
+        content_hash,
+        pokedex.commit_hash,
+        orphaned_entries,
+    ).execute(&mut *db).await?;
+
+    Ok(match previous {
+        None => FileStatus::Inserted {
+            pokedex_id: pokedex.id,
+            revision,
+            num_entries: pokedex.num_entries,
+        },
+        Some((previous_num_entries, _)) => FileStatus::Updated {
+            pokedex_id: pokedex.id,
+            revision,
+            previous_num_entries,
+            num_entries: pokedex.num_entries,
+        },
+    })
+}
+
+/// Moves the progress of all users from the entry IDs of the previous revision to the new ones.
+/// Progress on entries that don't exist anymore is moved to `orphaned_pokedex_progress`.
+/// Returns the IDs of the orphaned entries.
+async fn migrate_progress(
+    db: &mut MySqlConnection,
+    pokedex: &Pokedex,
+    previous_entries: &PokedexEntries,
+    revision: i32,
+) -> anyhow::Result<Vec<i32>> {
+    let mapping = pokedex.map_entries_from(previous_entries);
+    if mapping.is_identity() {
+        return Ok(vec![]);
+    }
+
+    // Entries may swap IDs, so rewriting the rows in place could collide with the
+    // primary key. Take all rows out and insert them again under their new IDs instead.
+    let progress = query!(
+        "select user_id, entry_id, source_pokedex_id from user_pokedex_progress where pokedex_id = ? for update",
+        pokedex.id
+    ).fetch_all(&mut *db).await?;
+    query!("delete from user_pokedex_progress where pokedex_id = ?", pokedex.id)
+        .execute(&mut *db).await?;
+
+    let mut num_moved = 0;
+    let mut num_orphaned = 0;
+    for row in progress {
+        match mapping.ids.get(&row.entry_id) {
+            Some(&new_id) => {
+                query!(
+                    "
+                    insert ignore into user_pokedex_progress (user_id, pokedex_id, entry_id, source_pokedex_id)
+                    values (?, ?, ?, ?)
+                    ",
+                    row.user_id,
+                    pokedex.id,
+                    new_id,
+                    row.source_pokedex_id
+                ).execute(&mut *db).await?;
+                if new_id != row.entry_id {
+                    num_moved += 1;
+                }
+            }
+            None => {
+                query!(
+                    "insert ignore into orphaned_pokedex_progress values (?, ?, ?, ?)",
+                    row.user_id,
+                    pokedex.id,
+                    row.entry_id,
+                    revision
+                ).execute(&mut *db).await?;
+                num_orphaned += 1;
+            }
+        }
+    }
+
+    info!("Moved {} progress entries of pokedex {} to new entry IDs.", num_moved, pokedex.name);
+    if !mapping.orphaned.is_empty() {
+        warn!(
+            "Revision {} of pokedex {} orphaned the entries {:?}. {} progress entries were moved to orphaned_pokedex_progress.",
+            revision, pokedex.name, mapping.orphaned, num_orphaned
+        );
+    }
+    Ok(mapping.orphaned)
+}
+
+async fn update_pokedex_table(
+    db: &mut MySqlConnection,
+    pokedex: &Pokedex,
+    revision: i32,
+    content_hash: &str,
+) -> anyhow::Result<()> {
+    let entries = serde_json::to_value(&pokedex.entries)?;
+    query!(
+        "
+        insert into pokedex (
+            id, name, description, num_entries, thumbnail_url, spritesheet_url,
+            commit_hash, entries, revision, content_hash
+        )
+        values (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        on duplicate key update
+            name = ?,
+            description = ?,
+            num_entries = ?,
+            thumbnail_url = ?,
+            spritesheet_url = ?,
+            commit_hash = ?,
+            entries = ?,
+            revision = ?,
+            content_hash = ?
+        ", 
+        // Insert
+        pokedex.id,
+        pokedex.name,
+        pokedex.description, 
+        pokedex.num_entries, 
+        pokedex.thumbnail_url.as_str(),
+        pokedex.spritesheet_url.as_str(),
+        pokedex.commit_hash.as_str(),
+        entries,
+        revision,
+        content_hash,
+        // Update
+        pokedex.name,
+        pokedex.description, 
+        pokedex.num_entries, 
+        pokedex.thumbnail_url.as_str(),
+        pokedex.spritesheet_url.as_str(),
+        pokedex.commit_hash.as_str(),
+        entries,
+        revision,
+        content_hash,
+    ).execute(db).await?;
+    Ok(())
+}
+
+/// Logs which revision of each pokedex this instance is serving.
+async fn log_pokedex_revisions(db: &MySqlPool) -> anyhow::Result<()> {
+    let revisions = query!(
+        "
+        select
+            pokedex.name,
+            pokedex.revision,
+            date_format(pokedex_revision.applied_at, '%Y-%m-%d %H:%i:%s') as 'applied_at!'
+        from pokedex, pokedex_revision
+        where
+            pokedex_revision.pokedex_id = pokedex.id and
+            pokedex_revision.revision = pokedex.revision
+        "
+    ).fetch_all(db).await?;
+    for revision in revisions {
+        info!("Serving pokedex {} at revision {}, applied at {}.", revision.name, revision.revision, revision.applied_at);
+    }
+    Ok(())
+}
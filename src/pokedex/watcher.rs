@@ -0,0 +1,51 @@
+use std::path::Path;
+use std::time::Duration;
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tracing::{debug, error, info, warn};
+use super::sync::{PokedexSynchronizer, SyncMode, DEFINITION_DIRECTORY};
+
+/// How long to wait for more changes before synchronizing.
+/// Copying a set of definitions causes a burst of events that should only trigger one sync.
+const DEBOUNCE_DELAY: Duration = Duration::from_secs(1);
+
+/// Watches the definition directory and synchronizes the database whenever it changes.
+/// If the directory can't be watched, e.g., because it does not exist, this only logs a warning.
+/// Failing synchronizations are logged, but never stop the watcher or the server.
+pub fn watch_definitions(synchronizer: PokedexSynchronizer) {
+    let (sender, mut receiver) = mpsc::unbounded_channel();
+    let watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        match event {
+            // The receiver only goes away when the server shuts down.
+            Ok(_) => { sender.send(()).ok(); },
+            Err(err) => warn!("Error while watching pokedex definitions: {}", err),
+        }
+    });
+    let mut watcher = match watcher {
+        Ok(watcher) => watcher,
+        Err(err) => {
+            warn!("Failed to create a watcher for pokedex definitions: {}", err);
+            return;
+        }
+    };
+    if let Err(err) = watcher.watch(Path::new(DEFINITION_DIRECTORY), RecursiveMode::NonRecursive) {
+        warn!("Not watching pokedex definitions in {}: {}", DEFINITION_DIRECTORY, err);
+        return;
+    }
+    info!("Watching {} for pokedex definition changes.", DEFINITION_DIRECTORY);
+
+    tokio::task::spawn(async move {
+        // The watcher stops once it is dropped, so it has to live as long as this task.
+        let _watcher = watcher;
+        while receiver.recv().await.is_some() {
+            tokio::time::sleep(DEBOUNCE_DELAY).await;
+            while receiver.try_recv().is_ok() {}
+
+            debug!("Pokedex definitions changed, synchronizing.");
+            match synchronizer.sync(SyncMode::Apply).await {
+                Ok(report) => report.log(),
+                Err(err) => error!("Failed to synchronize pokedex definitions: {:#}", err),
+            }
+        }
+    });
+}
@@ -0,0 +1,133 @@
+use askama::Template;
+use axum::Router;
+use axum::routing::get;
+use axum::response::IntoResponse;
+use axum::extract::{Query, State};
+use axum_login::permission_required;
+use serde::{Deserialize, Serialize};
+use sqlx::{query_as, FromRow, MySqlPool};
+
+use crate::auth::{AuthBackend, Permission};
+use crate::error::AppError;
+use crate::web::AppState;
+use crate::web::audit::AuditAction;
+
+/// How many records are shown at once.
+const PAGE_SIZE: u32 = 100;
+
+/// Narrows down the records of the audit log. Empty fields match everything.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+struct AuditFilter {
+    action: String,
+    actor: String,
+    target: String,
+    ip: String,
+    /// Only show records older than this one, to page through the log.
+    before: Option<i64>,
+}
+
+/// A record of the audit log.
+#[derive(Debug, Serialize, FromRow)]
+struct AuditRecord {
+    audit_id: i64,
+    time: String,
+    action: AuditAction,
+    actor: String,
+    target: Option<String>,
+    details: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_audit.html")]
+struct AuditTemplate {
+    filter: AuditFilter,
+    actions: Vec<&'static str>,
+    records: Vec<AuditRecord>,
+    /// Where the next page starts, if there are more records.
+    next_page: Option<i64>,
+}
+
+impl AuditTemplate {
+    fn is_selected(&self, action: &str) -> bool {
+        self.filter.action == action
+    }
+}
+
+/// Build a router for viewing the audit log.
+/// All routes require `ViewAuditLog`.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/audit", get(get::audit_page))
+        .route("/admin/api/audit", get(get::audit))
+        .route_layer(permission_required!(AuthBackend, Permission::ViewAuditLog))
+}
+
+/// Loads a page of the records that match `filter`, newest first.
+async fn load_records(db: &MySqlPool, filter: &AuditFilter) -> Result<Vec<AuditRecord>, AppError> {
+    let records = query_as!(
+        AuditRecord,
+        "
+        select
+            audit_id,
+            date_format(time, '%Y-%m-%d %H:%i:%s') as 'time!',
+            action as 'action: AuditAction',
+            actor,
+            target,
+            details,
+            ip,
+            user_agent
+        from audit_log
+        where (? = '' or action = ?)
+            and (? = '' or actor = ?)
+            and (? = '' or target = ?)
+            and (? = '' or ip = ?)
+            and (? is null or audit_id < ?)
+        order by audit_id desc
+        limit ?
+        ",
+        filter.action, filter.action,
+        filter.actor, filter.actor,
+        filter.target, filter.target,
+        filter.ip, filter.ip,
+        filter.before, filter.before,
+        PAGE_SIZE
+    ).fetch_all(db).await?;
+    Ok(records)
+}
+
+mod get {
+
+    use super::*;
+    use axum::response::Html;
+    use axum::Json;
+
+    /// Shows the audit log along with a form to filter it.
+    pub async fn audit_page(
+        State(state): State<AppState>,
+        Query(filter): Query<AuditFilter>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let records = load_records(&state.database, &filter).await?;
+        let next_page = match records.last() {
+            Some(record) if records.len() == PAGE_SIZE as usize => Some(record.audit_id),
+            _ => None,
+        };
+        Ok(Html(AuditTemplate {
+            filter,
+            actions: AuditAction::ALL.iter().map(AuditAction::name).collect(),
+            records,
+            next_page,
+        }.render()?))
+    }
+
+    /// Lists the records of the audit log as JSON.
+    /// Like the page, this takes a filter and returns the newest matching records.
+    pub async fn audit(
+        State(state): State<AppState>,
+        Query(filter): Query<AuditFilter>,
+    ) -> Result<impl IntoResponse, AppError> {
+        Ok(Json(load_records(&state.database, &filter).await?))
+    }
+}
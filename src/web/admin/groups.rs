@@ -0,0 +1,317 @@
+use std::collections::BTreeMap;
+use askama::Template;
+use axum::{Extension, Router};
+use axum::routing::{get, put, delete};
+use axum::response::IntoResponse;
+use axum::extract::{Path, State};
+use axum_login::permission_required;
+use http::StatusCode;
+use serde::Serialize;
+use sqlx::{query, query_scalar, MySqlConnection, MySqlPool};
+use tracing::info;
+
+use crate::auth::{AuthBackend, AuthSession, Permission, ADMIN_GROUP};
+use crate::error::AppError;
+use crate::web::AppState;
+use crate::web::audit::{AuditAction, AuditLog};
+use crate::web::csrf::CsrfToken;
+
+/// The maximum length of a group name. This is limited by the `group` columns.
+const MAX_GROUP_NAME_LENGTH: usize = 128;
+
+/// A group along with its members and permissions.
+#[derive(Debug, Serialize)]
+struct Group {
+    name: String,
+    members: Vec<String>,
+    permissions: Vec<Permission>,
+}
+
+/// Whether a group has a permission.
+#[derive(Debug)]
+struct PermissionView {
+    name: &'static str,
+    granted: bool,
+}
+
+/// A group as shown on the administration page.
+#[derive(Debug)]
+struct GroupView {
+    name: String,
+    members: Vec<String>,
+    /// Every permission there is, so it can be granted or revoked.
+    permissions: Vec<PermissionView>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_groups.html")]
+struct GroupsTemplate {
+    groups: Vec<GroupView>,
+    admin_group: &'static str,
+    /// Has to be sent along with requests that change something.
+    csrf_token: String,
+}
+
+/// Build a router for the group administration.
+/// Viewing groups and adding users or permissions requires `AddRole`,
+/// removing them requires `RemoveRole`.
+pub fn router() -> Router<AppState> {
+    let add_routes = Router::new()
+        .route("/admin/groups", get(get::groups_page))
+        .route("/admin/api/groups", get(get::groups))
+        .route("/admin/api/groups/{group}/users/{username}", put(put::member))
+        .route("/admin/api/groups/{group}/permissions/{permission}", put(put::permission))
+        .route_layer(permission_required!(AuthBackend, Permission::AddRole));
+    let remove_routes = Router::new()
+        .route("/admin/api/groups/{group}/users/{username}", delete(delete::member))
+        .route("/admin/api/groups/{group}/permissions/{permission}", delete(delete::permission))
+        .route_layer(permission_required!(AuthBackend, Permission::RemoveRole));
+    add_routes.merge(remove_routes)
+}
+
+/// Group names end up in URLs, so they are limited to a URL-safe set of characters.
+fn validate_group_name(name: &str) -> Result<(), AppError> {
+    let is_valid = !name.is_empty()
+        && name.chars().count() <= MAX_GROUP_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    if is_valid { Ok(()) } else { Err(AppError::BadRequest) }
+}
+
+/// Loads every group that has at least one member or permission.
+async fn load_groups(db: &MySqlPool) -> Result<Vec<Group>, AppError> {
+    let members = query!(
+        "
+        select user_group.`group` as `group`, user.name
+        from user_group, user
+        where user_group.user_id = user.user_id
+        order by user.name
+        "
+    ).fetch_all(db).await?;
+    let permissions = query!(
+        "select `group`, permission as 'permission: Permission' from group_permission"
+    ).fetch_all(db).await?;
+
+    let mut groups = BTreeMap::new();
+    for member in members {
+        groups.entry(member.group.clone())
+            .or_insert_with(|| Group { name: member.group, members: vec![], permissions: vec![] })
+            .members.push(member.name);
+    }
+    for permission in permissions {
+        groups.entry(permission.group.clone())
+            .or_insert_with(|| Group { name: permission.group, members: vec![], permissions: vec![] })
+            .permissions.push(permission.permission);
+    }
+    Ok(groups.into_values().collect())
+}
+
+/// Locks the grants of the role permissions until the end of the transaction.
+/// Every change that can leave no group to manage roles takes this lock first,
+/// so two admins can't take the permissions away from each other's groups at once.
+async fn lock_role_permissions(db: &mut MySqlConnection) -> Result<(), AppError> {
+    query!(
+        "select `group` from group_permission where permission in (?, ?) for update",
+        Permission::AddRole,
+        Permission::RemoveRole
+    ).fetch_all(db).await?;
+    Ok(())
+}
+
+/// Counts the groups with members that have both role permissions.
+/// If there are none left, nobody can manage groups anymore.
+async fn count_role_managers(db: &mut MySqlConnection) -> Result<i64, AppError> {
+    let role_managers = query_scalar!(
+        "select count(*) from (
+            select group_permission.`group` from group_permission
+            where permission in (?, ?)
+                and exists (select 1 from user_group where user_group.`group` = group_permission.`group`)
+            group by group_permission.`group`
+            having count(distinct permission) = 2
+        ) role_managers",
+        Permission::AddRole,
+        Permission::RemoveRole
+    ).fetch_one(db).await?;
+    Ok(role_managers)
+}
+
+mod get {
+
+    use super::*;
+    use axum::response::Html;
+    use axum::Json;
+
+    /// Shows all groups along with forms to edit them.
+    pub async fn groups_page(
+        State(state): State<AppState>,
+        CsrfToken(csrf_token): CsrfToken,
+    ) -> Result<impl IntoResponse, AppError> {
+        let groups = load_groups(&state.database).await?
+            .into_iter()
+            .map(|group| GroupView {
+                permissions: Permission::ALL
+                    .iter()
+                    .map(|permission| PermissionView {
+                        name: permission.name(),
+                        granted: group.permissions.contains(permission),
+                    })
+                    .collect(),
+                name: group.name,
+                members: group.members,
+            })
+            .collect();
+        Ok(Html(GroupsTemplate {
+            groups,
+            admin_group: ADMIN_GROUP,
+            csrf_token,
+        }.render()?))
+    }
+
+    /// Lists all groups as JSON.
+    pub async fn groups(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+        Ok(Json(load_groups(&state.database).await?))
+    }
+}
+
+mod put {
+
+    use super::*;
+
+    /// Add a user to a group. The group is created if it does not exist yet.
+    pub async fn member(
+        Path((group, username)): Path<(String, String)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+        validate_group_name(&group)?;
+
+        let user_id = query_scalar!("select user_id from user where name = ?", username)
+            .fetch_optional(&state.database).await?;
+        let user_id = user_id.ok_or(AppError::NotFound)?;
+
+        let is_member = query_scalar!(
+            "select count(*) from user_group where user_id = ? and `group` = ?",
+            user_id,
+            group
+        ).fetch_one(&state.database).await? > 0;
+        if is_member {
+            return Err(AppError::AlreadyExists);
+        }
+
+        query!("insert into user_group values (?, ?)", user_id, group)
+            .execute(&state.database).await?;
+
+        if let Some(actor) = auth_session.user {
+            info!("User {} has added {} to group {}.", actor.name, username, group);
+            audit.record(AuditAction::GroupMemberAdd, &actor.name, Some(&username), Some(&group)).await;
+        }
+        Ok(StatusCode::OK)
+    }
+
+    /// Grant a permission to a group. The group is created if it does not exist yet.
+    pub async fn permission(
+        Path((group, permission)): Path<(String, Permission)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+        validate_group_name(&group)?;
+
+        let is_granted = query_scalar!(
+            "select count(*) from group_permission where `group` = ? and permission = ?",
+            group,
+            permission
+        ).fetch_one(&state.database).await? > 0;
+        if is_granted {
+            return Err(AppError::AlreadyExists);
+        }
+
+        query!("insert into group_permission values (?, ?)", group, permission)
+            .execute(&state.database).await?;
+
+        if let Some(actor) = auth_session.user {
+            info!("User {} has granted {} to group {}.", actor.name, permission.name(), group);
+            audit.record(AuditAction::PermissionGrant, &actor.name, Some(&group), Some(permission.name())).await;
+        }
+        Ok(StatusCode::OK)
+    }
+}
+
+mod delete {
+
+    use super::*;
+
+    /// Remove a user from a group.
+    /// The last member of the admin group can't be removed, and neither can the last member
+    /// of the last group that has both role permissions.
+    pub async fn member(
+        Path((group, username)): Path<(String, String)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user_id = query_scalar!("select user_id from user where name = ?", username)
+            .fetch_optional(&state.database).await?;
+        let user_id = user_id.ok_or(AppError::NotFound)?;
+
+        // Lock the memberships of the group, so two admins can't remove each other at once.
+        let mut tx = state.database.begin().await?;
+        lock_role_permissions(&mut *tx).await?;
+        let members = query_scalar!(
+            "select user_id from user_group where `group` = ? for update",
+            group
+        ).fetch_all(&mut *tx).await?;
+        if !members.contains(&user_id) {
+            return Err(AppError::NotFound);
+        }
+        if group == ADMIN_GROUP && members.len() <= 1 {
+            return Err(AppError::Conflict);
+        }
+
+        query!("delete from user_group where user_id = ? and `group` = ?", user_id, group)
+            .execute(&mut *tx).await?;
+        if count_role_managers(&mut *tx).await? == 0 {
+            return Err(AppError::Conflict);
+        }
+        tx.commit().await?;
+
+        if let Some(actor) = auth_session.user {
+            info!("User {} has removed {} from group {}.", actor.name, username, group);
+            audit.record(AuditAction::GroupMemberRemove, &actor.name, Some(&username), Some(&group)).await;
+        }
+        Ok(StatusCode::OK)
+    }
+
+    /// Revoke a permission from a group.
+    /// The role permissions can't be revoked if no group with members would keep both of them.
+    pub async fn permission(
+        Path((group, permission)): Path<(String, Permission)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let mut tx = state.database.begin().await?;
+        lock_role_permissions(&mut *tx).await?;
+
+        let result = query!(
+            "delete from group_permission where `group` = ? and permission = ?",
+            group,
+            permission
+        ).execute(&mut *tx).await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+
+        let is_role_permission = matches!(permission, Permission::AddRole | Permission::RemoveRole);
+        if is_role_permission && count_role_managers(&mut *tx).await? == 0 {
+            return Err(AppError::Conflict);
+        }
+        tx.commit().await?;
+
+        if let Some(actor) = auth_session.user {
+            info!("User {} has revoked {} from group {}.", actor.name, permission.name(), group);
+            audit.record(AuditAction::PermissionRevoke, &actor.name, Some(&group), Some(permission.name())).await;
+        }
+        Ok(StatusCode::OK)
+    }
+}
@@ -0,0 +1,122 @@
+use askama::Template;
+use axum::{Extension, Router};
+use axum::routing::{get, delete};
+use axum::response::IntoResponse;
+use axum::extract::{Path, State};
+use axum_login::permission_required;
+use http::StatusCode;
+use serde::Serialize;
+use sqlx::{query, query_as, FromRow, MySqlPool};
+use tracing::info;
+
+use crate::auth::{AuthBackend, AuthSession, Permission};
+use crate::error::AppError;
+use crate::web::AppState;
+use crate::web::audit::{AuditAction, AuditLog};
+use crate::web::csrf::CsrfToken;
+use crate::web::throttle::{ThrottleKind, FAILURE_RESET};
+
+/// The failed logins of an account or an address.
+#[derive(Debug, Serialize, FromRow)]
+struct Lockout {
+    kind: ThrottleKind,
+    subject: String,
+    failures: i32,
+    last_failure: String,
+    /// Set as long as logins are rejected.
+    locked_until: Option<String>,
+}
+
+#[derive(Template)]
+#[template(path = "admin_lockouts.html")]
+struct LockoutsTemplate {
+    lockouts: Vec<Lockout>,
+    /// Has to be sent along with requests that change something.
+    csrf_token: String,
+}
+
+/// Build a router for the lockout administration.
+/// All routes require `ManageLockouts`.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/lockouts", get(get::lockouts_page))
+        .route("/admin/api/lockouts", get(get::lockouts))
+        .route("/admin/api/lockouts/{kind}/{subject}", delete(delete::lockout))
+        .route_layer(permission_required!(AuthBackend, Permission::ManageLockouts))
+}
+
+/// Loads every account and address with failures that aren't forgotten yet.
+/// Current lockouts come first.
+async fn load_lockouts(db: &MySqlPool) -> Result<Vec<Lockout>, AppError> {
+    let lockouts = query_as!(
+        Lockout,
+        "
+        select
+            kind as 'kind: ThrottleKind',
+            subject,
+            failures,
+            date_format(last_failure, '%Y-%m-%d %H:%i:%s') as 'last_failure!',
+            if(
+                locked_until > current_timestamp,
+                date_format(locked_until, '%Y-%m-%d %H:%i:%s'),
+                null
+            ) as locked_until
+        from login_throttle
+        where last_failure >= current_timestamp - interval ? second
+        order by login_throttle.locked_until > current_timestamp desc, login_throttle.last_failure desc
+        ",
+        FAILURE_RESET.as_secs()
+    ).fetch_all(db).await?;
+    Ok(lockouts)
+}
+
+mod get {
+
+    use super::*;
+    use axum::response::Html;
+    use axum::Json;
+
+    /// Shows the accounts and addresses with failed logins along with buttons to clear them.
+    pub async fn lockouts_page(
+        State(state): State<AppState>,
+        CsrfToken(csrf_token): CsrfToken,
+    ) -> Result<impl IntoResponse, AppError> {
+        Ok(Html(LockoutsTemplate {
+            lockouts: load_lockouts(&state.database).await?,
+            csrf_token,
+        }.render()?))
+    }
+
+    /// Lists the accounts and addresses with failed logins as JSON.
+    pub async fn lockouts(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+        Ok(Json(load_lockouts(&state.database).await?))
+    }
+}
+
+mod delete {
+
+    use super::*;
+
+    /// Forget the failed logins of an account or an address, which also lifts its lockout.
+    pub async fn lockout(
+        Path((kind, subject)): Path<(ThrottleKind, String)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let result = query!(
+            "delete from login_throttle where kind = ? and subject = ?",
+            kind,
+            subject
+        ).execute(&state.database).await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+
+        if let Some(actor) = auth_session.user {
+            info!("User {} has cleared the failed logins of {} {}.", actor.name, kind.name(), subject);
+            audit.record(AuditAction::LockoutClear, &actor.name, Some(&subject), Some(kind.name())).await;
+        }
+        Ok(StatusCode::OK)
+    }
+}
@@ -0,0 +1,17 @@
+mod audit;
+mod groups;
+mod lockouts;
+mod pokedexes;
+
+use axum::Router;
+use crate::web::AppState;
+
+/// Build a router for all administration routes.
+/// Each sub-router guards its routes with the permissions they require.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .merge(audit::router())
+        .merge(groups::router())
+        .merge(lockouts::router())
+        .merge(pokedexes::router())
+}
@@ -0,0 +1,54 @@
+use axum::Router;
+use axum::routing::post;
+use axum::response::IntoResponse;
+use axum::extract::{Query, State};
+use axum::Json;
+use axum_login::permission_required;
+use serde::Deserialize;
+use tracing::info;
+
+use crate::auth::{AuthBackend, AuthSession, Permission};
+use crate::error::AppError;
+use crate::pokedex::SyncMode;
+use crate::web::AppState;
+
+#[derive(Debug, Deserialize)]
+struct ReloadOptions {
+    /// Only report what the reload would change.
+    #[serde(default)]
+    dry_run: bool,
+}
+
+/// Build a router for the pokedex administration.
+/// All routes require `ManagePokedexes`.
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/admin/api/pokedexes/reload", post(post::reload))
+        .route_layer(permission_required!(AuthBackend, Permission::ManagePokedexes))
+}
+
+mod post {
+
+    use super::*;
+
+    /// Synchronizes the pokedex definitions on disk with the database
+    /// and reports the outcome for every definition file.
+    /// With `dry_run`, nothing is written and the report shows what would change.
+    pub async fn reload(
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Query(options): Query<ReloadOptions>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let mode = if options.dry_run { SyncMode::DryRun } else { SyncMode::Apply };
+        if let Some(user) = auth_session.user {
+            if options.dry_run {
+                info!("User {} has triggered a pokedex reload dry run.", user.name);
+            } else {
+                info!("User {} has triggered a pokedex reload.", user.name);
+            }
+        }
+        let report = state.pokedex_sync.sync(mode).await?;
+        report.log();
+        Ok(Json(report))
+    }
+}
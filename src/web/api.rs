@@ -0,0 +1,178 @@
+use axum::{Json, Router};
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use serde::Serialize;
+use utoipa::{OpenApi, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+
+use crate::error::ErrorMessage;
+use crate::web::{login, pokedex, user, AppState};
+
+/// An error as returned by the API.
+#[derive(Debug, Serialize, ToSchema)]
+pub struct ErrorBody {
+    status: u16,
+    error: &'static str,
+}
+
+/// All API routes start with this prefix.
+const API_PREFIX: &str = "/api/";
+
+/// The OpenAPI document is served here.
+/// It is not versioned, because it describes every version of the API.
+const OPENAPI_PATH: &str = "/api/openapi.json";
+
+#[derive(OpenApi)]
+#[openapi(
+    info(title = "mydex", description = "Track your progress in Pokédexes."),
+    tags(
+        (name = "users", description = "Users and the progress in the Pokédexes on their profiles"),
+        (name = "pokedexes", description = "The catalog of Pokédexes"),
+        (name = "session", description = "Logging in with a session cookie"),
+    ),
+)]
+struct ApiDoc;
+
+/// Build the router of every documented route: the versioned API and the session routes.
+/// Routes can only be added along with their documentation, so the document can't
+/// miss a route. The export and import of the web interface and the administration
+/// endpoints under `/admin/api` are not part of the document.
+fn documented_router() -> OpenApiRouter<AppState> {
+    let v1 = OpenApiRouter::new()
+        .merge(user::api_router())
+        .merge(pokedex::api_router());
+    OpenApiRouter::with_openapi(ApiDoc::openapi())
+        .nest("/api/v1", v1)
+        .merge(login::router())
+}
+
+/// Build the router for the JSON API along with the login routes.
+/// Every version is nested under its own prefix, so old clients keep working when
+/// a new version changes the API.
+pub fn router() -> Router<AppState> {
+    let (router, openapi) = documented_router().split_for_parts();
+    router.route(OPENAPI_PATH, get(async move || Json(openapi.clone())))
+}
+
+/// Replaces the body of error responses to API requests with a JSON description of the error.
+/// The handlers are shared with the web interface, which renders errors as HTML.
+/// Rejections of extractors don't come from an `AppError`, so they are described by their status.
+/// This is a layer of the whole app, so errors of other layers, like a rejected API token,
+/// are covered as well.
+pub async fn json_errors(request: Request, next: Next) -> Response {
+    let is_api_request = request.uri().path().starts_with(API_PREFIX);
+    let response = next.run(request).await;
+    let status = response.status();
+    if !is_api_request || (!status.is_client_error() && !status.is_server_error()) {
+        return response;
+    }
+    let error = match response.extensions().get::<ErrorMessage>() {
+        Some(ErrorMessage(message)) => *message,
+        None => status.canonical_reason().unwrap_or("Error"),
+    };
+    (status, Json(ErrorBody { status: status.as_u16(), error })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeSet;
+    use std::time::Duration;
+    use axum::body::Body;
+    use axum::http::{Method, StatusCode};
+    use sqlx::mysql::MySqlPoolOptions;
+    use tower::ServiceExt;
+    use utoipa::openapi::PathItem;
+
+    use super::*;
+    use crate::pokedex::PokedexSynchronizer;
+    use crate::web::session::SessionPolicy;
+
+    /// Every operation of the document. Adding a route to the documented router
+    /// means adding it here as well, so a route can't slip in unnoticed.
+    const OPERATIONS: [(Method, &str); 13] = [
+        (Method::GET, "/api/v1/pokedexes"),
+        (Method::GET, "/api/v1/pokedexes/{pokedex_id}"),
+        (Method::GET, "/api/v1/users/{username}"),
+        (Method::GET, "/api/v1/users/{username}/pokedexes/{pokedex_id}"),
+        (Method::PUT, "/api/v1/users/{username}/pokedexes/{pokedex_id}"),
+        (Method::DELETE, "/api/v1/users/{username}/pokedexes/{pokedex_id}"),
+        (Method::POST, "/api/v1/users/{username}/pokedexes/{pokedex_id}/entries"),
+        (Method::PUT, "/api/v1/users/{username}/pokedexes/{pokedex_id}/entries/{entry_id}"),
+        (Method::DELETE, "/api/v1/users/{username}/pokedexes/{pokedex_id}/entries/{entry_id}"),
+        (Method::POST, "/api/v1/users/{username}/pokedexes/{pokedex_id}/entries/{entry_id}/linked"),
+        (Method::GET, "/login"),
+        (Method::POST, "/login"),
+        (Method::POST, "/logout"),
+    ];
+
+    /// The operations of a path in the document along with their method.
+    fn operations(item: &PathItem) -> Vec<Method> {
+        [
+            (Method::GET, &item.get),
+            (Method::PUT, &item.put),
+            (Method::POST, &item.post),
+            (Method::DELETE, &item.delete),
+            (Method::PATCH, &item.patch),
+        ]
+            .into_iter()
+            .filter(|(_, operation)| operation.is_some())
+            .map(|(method, _)| method)
+            .collect()
+    }
+
+    /// Routes are registered through the `OpenApiRouter`, so every route is documented.
+    /// This checks that the document contains exactly the expected operations.
+    #[test]
+    fn document_has_the_expected_operations() {
+        let (_, openapi) = documented_router().split_for_parts();
+        let documented: BTreeSet<(String, String)> = openapi.paths.paths
+            .iter()
+            .flat_map(|(path, item)| {
+                operations(item).into_iter().map(|method| (method.to_string(), path.clone()))
+            })
+            .collect();
+        let expected: BTreeSet<(String, String)> = OPERATIONS
+            .iter()
+            .map(|(method, path)| (method.to_string(), path.to_string()))
+            .collect();
+        assert_eq!(documented, expected);
+    }
+
+    /// Every documented operation must be routed.
+    /// A missing route falls through to the fallback, a missing method is rejected with 405.
+    /// Neither needs a database, so the pool never connects.
+    #[tokio::test]
+    async fn documented_operations_are_routed() {
+        let pool = MySqlPoolOptions::new()
+            .acquire_timeout(Duration::from_millis(100))
+            .connect_lazy("mysql://localhost/mydex")
+            .unwrap();
+        let state = AppState {
+            database: pool.clone(),
+            pokedex_sync: PokedexSynchronizer::new(pool),
+            session_policy: SessionPolicy {
+                absolute_lifetime: time::Duration::days(7),
+                remember_lifetime: time::Duration::days(30),
+            },
+        };
+        let (router, openapi) = documented_router().split_for_parts();
+        let router = router.fallback(async || StatusCode::IM_A_TEAPOT).with_state(state);
+
+        assert!(!openapi.paths.paths.is_empty());
+        for (path, item) in &openapi.paths.paths {
+            // Every path parameter of the API accepts a number.
+            let uri = path.split('/')
+                .map(|segment| if segment.starts_with('{') { "1" } else { segment })
+                .collect::<Vec<_>>()
+                .join("/");
+            for method in operations(item) {
+                let request = http::Request::builder().method(method.clone()).uri(&uri).body(Body::empty()).unwrap();
+                let status = router.clone().oneshot(request).await.unwrap().status();
+                assert_ne!(status, StatusCode::IM_A_TEAPOT, "{} {} is documented, but not routed", method, path);
+                assert_ne!(status, StatusCode::METHOD_NOT_ALLOWED, "{} {} is documented, but not routed", method, path);
+            }
+        }
+    }
+}
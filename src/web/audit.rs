@@ -0,0 +1,145 @@
+use std::net::SocketAddr;
+use axum::extract::{ConnectInfo, Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use http::header;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, MySqlPool};
+use tracing::error;
+
+/// The lengths of the text columns of the `audit_log` table.
+const MAX_ACTOR_LENGTH: usize = 64;
+const MAX_TARGET_LENGTH: usize = 128;
+const MAX_DETAILS_LENGTH: usize = 256;
+const MAX_USER_AGENT_LENGTH: usize = 512;
+
+/// A security-relevant action that is recorded in the audit log.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum AuditAction {
+    Login,
+    /// A wrong password, an unknown user or a lockout.
+    LoginFailed,
+    Logout,
+    PasswordChange,
+    /// The old name is the actor, the new one the target.
+    UsernameChange,
+    ApiTokenCreate,
+    ApiTokenRevoke,
+    /// Adding a Pokédex to another user's profile.
+    PokedexAdd,
+    /// Removing a Pokédex from another user's profile.
+    PokedexRemove,
+    /// Marking entries on another user's profile.
+    ProgressEdit,
+    /// Importing data into another user's profile.
+    ProgressImport,
+    GroupMemberAdd,
+    GroupMemberRemove,
+    PermissionGrant,
+    PermissionRevoke,
+    LockoutClear,
+}
+
+impl AuditAction {
+    /// Every action there is. Used to filter by action in the administration UI.
+    pub const ALL: [AuditAction; 16] = [
+        AuditAction::Login,
+        AuditAction::LoginFailed,
+        AuditAction::Logout,
+        AuditAction::PasswordChange,
+        AuditAction::UsernameChange,
+        AuditAction::ApiTokenCreate,
+        AuditAction::ApiTokenRevoke,
+        AuditAction::PokedexAdd,
+        AuditAction::PokedexRemove,
+        AuditAction::ProgressEdit,
+        AuditAction::ProgressImport,
+        AuditAction::GroupMemberAdd,
+        AuditAction::GroupMemberRemove,
+        AuditAction::PermissionGrant,
+        AuditAction::PermissionRevoke,
+        AuditAction::LockoutClear,
+    ];
+
+    /// The name of the action as it is stored in the database.
+    pub fn name(&self) -> &'static str {
+        match self {
+            AuditAction::Login => "login",
+            AuditAction::LoginFailed => "login_failed",
+            AuditAction::Logout => "logout",
+            AuditAction::PasswordChange => "password_change",
+            AuditAction::UsernameChange => "username_change",
+            AuditAction::ApiTokenCreate => "api_token_create",
+            AuditAction::ApiTokenRevoke => "api_token_revoke",
+            AuditAction::PokedexAdd => "pokedex_add",
+            AuditAction::PokedexRemove => "pokedex_remove",
+            AuditAction::ProgressEdit => "progress_edit",
+            AuditAction::ProgressImport => "progress_import",
+            AuditAction::GroupMemberAdd => "group_member_add",
+            AuditAction::GroupMemberRemove => "group_member_remove",
+            AuditAction::PermissionGrant => "permission_grant",
+            AuditAction::PermissionRevoke => "permission_revoke",
+            AuditAction::LockoutClear => "lockout_clear",
+        }
+    }
+}
+
+/// Records actions to the audit log along with where the request came from.
+/// The `audit_context` layer adds this to every request, so handlers can take it as an `Extension`.
+#[derive(Clone)]
+pub struct AuditLog {
+    db: MySqlPool,
+    ip: Option<String>,
+    user_agent: Option<String>,
+}
+
+impl AuditLog {
+    /// Records that `actor` has done `action` to `target`.
+    /// Overlong values are cut off rather than failing the action.
+    /// Actions are recorded after they have been applied, so a failure to record one is
+    /// logged instead of failing a request whose change went through anyway.
+    pub async fn record(
+        &self,
+        action: AuditAction,
+        actor: &str,
+        target: Option<&str>,
+        details: Option<&str>,
+    ) {
+        let result = query!(
+            "insert into audit_log (action, actor, target, details, ip, user_agent) values (?, ?, ?, ?, ?, ?)",
+            action,
+            truncate(actor, MAX_ACTOR_LENGTH),
+            target.map(|target| truncate(target, MAX_TARGET_LENGTH)),
+            details.map(|details| truncate(details, MAX_DETAILS_LENGTH)),
+            self.ip,
+            self.user_agent
+        ).execute(&self.db).await;
+        if let Err(err) = result {
+            error!("Failed to record {} by {} in the audit log: {}", action.name(), actor, err);
+        }
+    }
+}
+
+/// Cuts `value` off after `max_length` characters.
+fn truncate(value: &str, max_length: usize) -> &str {
+    match value.char_indices().nth(max_length) {
+        Some((end, _)) => &value[..end],
+        None => value,
+    }
+}
+
+/// Adds an `AuditLog` for the client of the request to the request extensions.
+/// The address of the client is only known if the server was started with connect info.
+pub async fn audit_context(State(db): State<MySqlPool>, mut request: Request, next: Next) -> Response {
+    let ip = request.extensions()
+        .get::<ConnectInfo<SocketAddr>>()
+        .map(|ConnectInfo(address)| address.ip().to_string());
+    let user_agent = request.headers()
+        .get(header::USER_AGENT)
+        .and_then(|user_agent| user_agent.to_str().ok())
+        .map(|user_agent| truncate(user_agent, MAX_USER_AGENT_LENGTH).to_string());
+    request.extensions_mut().insert(AuditLog { db, ip, user_agent });
+    next.run(request).await
+}
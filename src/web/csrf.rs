@@ -0,0 +1,143 @@
+use axum::body::{to_bytes, Body};
+use axum::extract::{FromRequest, FromRequestParts, Request};
+use axum::http::request::Parts;
+use axum::middleware::Next;
+use axum::response::Response;
+use axum::Form;
+use base64::Engine;
+use http::{header, Method};
+use rand::rngs::OsRng;
+use rand::RngCore;
+use serde::Deserialize;
+use tower_sessions::Session;
+use tracing::debug;
+
+use crate::auth::TokenScope;
+use crate::error::AppError;
+
+/// The session key of the synchronizer token.
+const SESSION_KEY: &str = "csrf_token";
+
+/// Scripts send the token in this header.
+const TOKEN_HEADER: &str = "x-csrf-token";
+
+/// Forms are read into memory to find the token, so their size is limited.
+const MAX_FORM_LENGTH: usize = 1024 * 1024;
+
+/// The token field of forms. The `csrf::field` template macro adds it.
+#[derive(Deserialize)]
+struct TokenForm {
+    csrf_token: String,
+}
+
+/// The synchronizer token of the session, to be embedded into pages with forms or scripts
+/// that change something. The token is created along with the session if there is none yet.
+pub struct CsrfToken(pub String);
+
+impl<S: Send + Sync> FromRequestParts<S> for CsrfToken {
+    type Rejection = AppError;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let session = parts.extensions.get::<Session>()
+            .ok_or_else(|| anyhow::anyhow!("The session is missing. Is the session layer set up?"))?;
+        if let Some(token) = session.get::<String>(SESSION_KEY).await.map_err(anyhow::Error::from)? {
+            return Ok(CsrfToken(token));
+        }
+        let mut bytes = [0u8; 32];
+        OsRng.fill_bytes(&mut bytes);
+        let token = base64::engine::general_purpose::URL_SAFE_NO_PAD.encode(bytes);
+        session.insert(SESSION_KEY, &token).await.map_err(anyhow::Error::from)?;
+        Ok(CsrfToken(token))
+    }
+}
+
+/// Drops the token of the session, so the next page gets a new one.
+/// This has to happen whenever the session changes hands, i.e., on login and logout,
+/// so a token planted before can't be used afterwards.
+pub async fn rotate(session: &Session) -> Result<(), AppError> {
+    session.remove::<String>(SESSION_KEY).await.map_err(anyhow::Error::from)?;
+    Ok(())
+}
+
+/// Rejects requests that may change something unless they carry the synchronizer token of the
+/// session, either in the `X-CSRF-Token` header or in the `csrf_token` field of a form.
+/// Requests from other sites are rejected by their `Origin` or `Referer` as well.
+/// Requests authenticated with an API token don't rely on the session cookie, so they are exempt.
+/// This has to run inside of `bearer_auth`, which marks those requests with their `TokenScope`.
+pub async fn protect(request: Request, next: Next) -> Result<Response, AppError> {
+    let is_safe = matches!(*request.method(), Method::GET | Method::HEAD | Method::OPTIONS);
+    if is_safe || request.extensions().get::<TokenScope>().is_some() {
+        return Ok(next.run(request).await);
+    }
+    if !is_same_origin(&request) {
+        debug!("Rejected {} {} from another origin.", request.method(), request.uri().path());
+        return Err(AppError::Csrf);
+    }
+
+    let session = request.extensions().get::<Session>()
+        .ok_or_else(|| anyhow::anyhow!("The session is missing. Is the session layer set up?"))?;
+    let expected = session.get::<String>(SESSION_KEY).await.map_err(anyhow::Error::from)?;
+    let Some(expected) = expected else {
+        return Err(AppError::Csrf);
+    };
+
+    let header_token = request.headers()
+        .get(TOKEN_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_string);
+    let (request, token) = match header_token {
+        Some(token) => (request, Some(token)),
+        None => read_form_token(request).await?,
+    };
+    match token {
+        Some(token) if tokens_match(&token, &expected) => Ok(next.run(request).await),
+        _ => {
+            debug!("Rejected {} {} without a valid CSRF token.", request.method(), request.uri().path());
+            Err(AppError::Csrf)
+        }
+    }
+}
+
+/// Checks that a request comes from a page of this site.
+/// Browsers send the `Origin` of cross-site requests. Where they don't, the `Referer` is used.
+/// Requests with neither are left to the token.
+fn is_same_origin(request: &Request) -> bool {
+    let headers = request.headers();
+    let source = headers.get(header::ORIGIN).or_else(|| headers.get(header::REFERER));
+    let Some(source) = source else {
+        return true;
+    };
+    let host = headers.get(header::HOST).and_then(|host| host.to_str().ok());
+    let source_host = source.to_str().ok()
+        .and_then(|source| source.split_once("://"))
+        .and_then(|(_, rest)| rest.split('/').next());
+    host.is_some() && host == source_host
+}
+
+/// Reads the token from the body of a form and puts the body back into the request.
+async fn read_form_token(request: Request) -> Result<(Request, Option<String>), AppError> {
+    let is_form = request.headers()
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("application/x-www-form-urlencoded"));
+    if !is_form {
+        return Ok((request, None));
+    }
+
+    let (parts, body) = request.into_parts();
+    let bytes = to_bytes(body, MAX_FORM_LENGTH).await.map_err(|_| AppError::BadRequest)?;
+    let form_request = http::Request::post("/")
+        .header(header::CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(bytes.clone()))
+        .map_err(anyhow::Error::from)?;
+    let token = Form::<TokenForm>::from_request(form_request, &()).await
+        .ok()
+        .map(|Form(form)| form.csrf_token);
+    Ok((Request::from_parts(parts, Body::from(bytes)), token))
+}
+
+/// Compares tokens in constant time, so the comparison doesn't reveal how much of a guess is right.
+fn tokens_match(token: &str, expected: &str) -> bool {
+    token.len() == expected.len()
+        && token.bytes().zip(expected.bytes()).fold(0, |difference, (a, b)| difference | (a ^ b)) == 0
+}
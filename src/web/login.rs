@@ -1,16 +1,25 @@
+use std::net::SocketAddr;
 use askama::Template;
 use axum::{
-    extract::Query,
+    extract::{ConnectInfo, Query, State},
+    Extension,
     http::StatusCode,
     response::{IntoResponse, Redirect, Html},
-    routing::{get, post},
-    Form, Router,
+    Form,
 };
 use axum_messages::{Message, Messages};
 use serde::Deserialize;
+use tower_sessions::Session;
+use utoipa::IntoParams;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
 use crate::error::AppError;
 use crate::auth::{AuthSession, Credentials};
+use crate::web::AppState;
+use crate::web::audit::{AuditAction, AuditLog};
+use crate::web::csrf::{self, CsrfToken};
+use crate::web::throttle::{self, LoginAttempt};
 
 /// The login page HTML template.
 #[derive(Template)]
@@ -18,21 +27,24 @@ use crate::auth::{AuthSession, Credentials};
 struct LoginTemplate {
     messages: Vec<Message>,
     next: Option<String>,
+    /// Has to be sent along with requests that change something.
+    csrf_token: String,
 }
 
 /// This allows us to extract the "next" field from the query string. 
 /// We use this to redirect after login.
-#[derive(Debug, Deserialize)]
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
 struct NextUrl {
     next: Option<String>,
 }
 
 /// Build a router for all login-related routes.
-pub fn router() -> Router<()> {
-    Router::new()
-        .route("/login", post(post::login))
-        .route("/login", get(get::login))
-        .route("/logout", get(get::logout))
+/// The routes are part of the API documentation, since API clients may use a session as well.
+pub fn router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get::login, post::login))
+        .routes(routes!(post::logout))
 }
 
 
@@ -40,37 +52,78 @@ mod get {
     
     use super::*;
 
+    /// Shows the login page.
+    #[utoipa::path(
+        get,
+        path = "/login",
+        tag = "session",
+        params(NextUrl),
+        responses((status = 200, description = "The login page", content_type = "text/html", body = String)),
+    )]
     pub async fn login(
         messages: Messages,
         Query(NextUrl { next }): Query<NextUrl>,
+        CsrfToken(csrf_token): CsrfToken,
     ) -> Result<impl IntoResponse, AppError> {
         Ok(Html(LoginTemplate {
             messages: messages.into_iter().collect(),
             next,
+            csrf_token,
         }.render()?))
     }
 
-    pub async fn logout(mut auth_session: AuthSession) -> impl IntoResponse {
-        match auth_session.logout().await {
-            Ok(_) => Redirect::to("/").into_response(),
-            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
-        }
-    }
-
 }
 
 mod post {
     use tracing::error;
     use super::*;
     
+    /// Starts a session. The session cookie authenticates later requests.
+    /// Like every request that changes something with a session, this needs the CSRF token
+    /// of the session, which is embedded into the login page.
+    #[utoipa::path(
+        post,
+        path = "/login",
+        tag = "session",
+        request_body(content = Credentials, content_type = "application/x-www-form-urlencoded"),
+        responses(
+            (status = 303, description = "Logged in, redirects to `next` or the index page. \
+                Invalid credentials redirect to the login page. After too many failures, \
+                the account or address is locked out for a while and even valid credentials are rejected."),
+        ),
+    )]
     pub async fn login(
         mut auth_session: AuthSession,
         messages: Messages,
+        State(state): State<AppState>,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        Extension(audit): Extension<AuditLog>,
+        Extension(session): Extension<Session>,
         Form(creds): Form<Credentials>,
     ) -> impl IntoResponse {
-        let user = match auth_session.authenticate(creds.clone()).await {
+        let attempt = LoginAttempt::new(&creds.username, address.ip());
+        let is_locked = match throttle::is_locked(&state.database, &attempt).await {
+            Ok(is_locked) => is_locked,
+            Err(err) => {
+                error!("Failed to check for login lockouts: {}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response()
+            },
+        };
+
+        // Locked out attempts look just like wrong passwords, so they don't tell
+        // whether the password was right. They don't cost a password verification either.
+        let user = if is_locked { Ok(None) } else { auth_session.authenticate(creds.clone()).await };
+        let user = match user {
             Ok(Some(user)) => user,
             Ok(None) => {
+                if !is_locked {
+                    if let Err(err) = throttle::record_failure(&state.database, &attempt).await {
+                        error!("Failed to record failed login: {}", err);
+                        return StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                    }
+                }
+                let details = if is_locked { Some("locked out") } else { None };
+                audit.record(AuditAction::LoginFailed, &creds.username, None, details).await;
                 messages.error("Invalid credentials");
 
                 let mut login_url = "/login".to_string();
@@ -86,9 +139,26 @@ mod post {
             },
         };
 
+        if let Err(err) = throttle::record_success(&state.database, &attempt).await {
+            error!("Failed to record successful login: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+
         if auth_session.login(&user).await.is_err() {
             return StatusCode::INTERNAL_SERVER_ERROR.into_response();
         }
+        // A token planted before the login must not be valid for the logged-in session.
+        if let Err(err) = csrf::rotate(&session).await {
+            error!("Failed to rotate the CSRF token: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        if creds.remember.is_some() {
+            if let Err(err) = state.session_policy.remember(&session).await {
+                error!("Failed to remember session: {}", err);
+                return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+            }
+        }
+        audit.record(AuditAction::Login, &user.name, None, None).await;
         
         messages.success(format!("Successfully logged in as {}", user.name));
 
@@ -99,4 +169,30 @@ mod post {
         }.into_response()
     }
 
+    /// Ends the session and redirects to the index page.
+    /// Like logging in, this needs the CSRF token of the session, so other sites can't log users out.
+    #[utoipa::path(
+        post,
+        path = "/logout",
+        tag = "session",
+        responses((status = 303, description = "Logged out, redirects to the index page")),
+    )]
+    pub async fn logout(
+        mut auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+        Extension(session): Extension<Session>,
+    ) -> impl IntoResponse {
+        if let Some(user) = &auth_session.user {
+            audit.record(AuditAction::Logout, &user.name, None, None).await;
+        }
+        if let Err(err) = csrf::rotate(&session).await {
+            error!("Failed to rotate the CSRF token: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        match auth_session.logout().await {
+            Ok(_) => Redirect::to("/").into_response(),
+            Err(_) => StatusCode::INTERNAL_SERVER_ERROR.into_response(),
+        }
+    }
+
 }
\ No newline at end of file
@@ -1,6 +1,8 @@
+use std::net::SocketAddr;
+use std::path::Path;
 use anyhow::Context;
 use axum::response::IntoResponse;
-use axum::Router;
+use axum::{middleware, Router};
 use axum_login::AuthManagerLayerBuilder;
 use axum_messages::MessagesManagerLayer;
 use base64::Engine;
@@ -8,24 +10,35 @@ use sqlx::MySqlPool;
 use tokio::net::TcpListener;
 use tokio::signal;
 use tokio::task::{AbortHandle, JoinHandle};
-use tower_sessions::cookie::Key;
+use tower_sessions::cookie::{Key, SameSite};
 use tower_sessions::{ExpiredDeletion, Expiry, SessionManagerLayer};
 use tower_sessions_sqlx_store::MySqlStore;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 use crate::Config;
-use crate::auth::AuthBackend;
+use crate::auth::{Argon2Hasher, AuthBackend, PasswordPolicy};
 use crate::error::AppError;
-use crate::pokedex::update_pokedex_database;
+use crate::pokedex::{watch_definitions, PokedexSynchronizer, SyncMode};
+use crate::web::session::SessionPolicy;
 
+mod admin;
+mod api;
+mod audit;
+mod csrf;
 mod login;
+mod signup;
 mod user;
 mod index;
 mod r#static;
 mod pokedex;
+mod session;
+mod throttle;
+mod token;
 
 #[derive(Clone)]
 pub struct AppState {
     database: MySqlPool,
+    pokedex_sync: PokedexSynchronizer,
+    session_policy: SessionPolicy,
 }
 
 pub struct App {
@@ -43,9 +56,11 @@ impl App {
         info!("Running database migrations.");
         sqlx::migrate!().run(&pool).await?;
 
-        // Update definitions
+        // Update definitions and keep them up-to-date while running
         info!("Updating pokedexes.");
-        update_pokedex_database(pool.clone()).await?;
+        let pokedex_sync = PokedexSynchronizer::new(pool.clone());
+        pokedex_sync.sync(SyncMode::Apply).await?.log();
+        watch_definitions(pokedex_sync.clone());
 
         // Session layer
         // This uses `tower-sessions` to establish a layer that will provide the session
@@ -68,28 +83,58 @@ impl App {
             info!("Generating random cookie key.");
             Key::generate()
         };
-        let session_layer = SessionManagerLayer::new(session_store)
-            // It would be safe to send the cookie over unencrypted communication
-            .with_secure(false)
-            .with_expiry(Expiry::OnInactivity(time::Duration::days(1)))
+        let same_site = parse_same_site(&config.cookie_same_site)?;
+        if same_site == SameSite::None && !config.cookie_secure {
+            warn!("Browsers reject cookies with SameSite=None that aren't secure. Set COOKIE_SECURE.");
+        }
+        let mut session_layer = SessionManagerLayer::new(session_store)
+            .with_name(config.cookie_name.clone())
+            .with_secure(config.cookie_secure)
+            .with_same_site(same_site)
+            .with_path(config.cookie_path.clone())
+            .with_expiry(Expiry::OnInactivity(time::Duration::seconds(config.session_inactivity_lifetime.into())))
             // Encrypt and sign the cookie. The user does not need its contents.
             .with_private(key);
+        if let Some(domain) = &config.cookie_domain {
+            session_layer = session_layer.with_domain(domain.clone());
+        }
+        let session_policy = SessionPolicy {
+            absolute_lifetime: time::Duration::seconds(config.session_absolute_lifetime.into()),
+            remember_lifetime: time::Duration::seconds(config.session_remember_lifetime.into()),
+        };
 
         // Auth layer
         // This combines the session layer with our auth backend to establish the auth
         // service which will provide the auth session as a request extension.
-        let backend = AuthBackend::new(pool.clone());
+        let hasher = Argon2Hasher::new(
+            config.argon2_memory_cost,
+            config.argon2_time_cost,
+            config.argon2_parallelism,
+        )?;
+        let mut password_policy = PasswordPolicy::new(config.password_min_length, config.password_max_length);
+        if let Some(path) = &config.password_breached_list {
+            password_policy = password_policy.with_breached_list(Path::new(path))?;
+            info!("Loaded {} breached passwords.", password_policy.num_breached());
+        }
+        let backend = AuthBackend::new(pool.clone(), hasher, password_policy);
         let auth_layer = AuthManagerLayerBuilder::new(backend, session_layer).build();
 
         // Create the app's router
-        let app_state = AppState { database: pool };
+        let app_state = AppState { database: pool.clone(), pokedex_sync, session_policy };
         let router = Router::new()
             .merge(user::router())
+            .merge(admin::router())
+            .merge(api::router())
             .with_state(app_state)
             .merge(index::router())
-            .merge(login::router())
+            .merge(signup::router())
             .merge(r#static::router())
             .fallback(async || AppError::NotFound.into_response())
+            .layer(middleware::from_fn(csrf::protect))
+            .layer(middleware::from_fn(token::bearer_auth))
+            .layer(middleware::from_fn_with_state(session_policy, session::enforce_deadline))
+            .layer(middleware::from_fn_with_state(pool, audit::audit_context))
+            .layer(middleware::from_fn(api::json_errors))
             .layer(MessagesManagerLayer)
             .layer(auth_layer);
 
@@ -108,7 +153,8 @@ impl App {
         info!("Starting server on {}.", self.config.bind_addr);
 
         // Ensure we use a shutdown signal to abort the deletion task.
-        axum::serve(listener, self.router.into_make_service())
+        // The address of the client is needed to throttle failed logins.
+        axum::serve(listener, self.router.into_make_service_with_connect_info::<SocketAddr>())
             .with_graceful_shutdown(shutdown_signal(self.session_deletion_task.abort_handle()))
             .await?;
 
@@ -121,6 +167,16 @@ impl App {
     }
 }
 
+/// Parses the SameSite mode of the session cookie.
+fn parse_same_site(value: &str) -> anyhow::Result<SameSite> {
+    match value.to_lowercase().as_str() {
+        "strict" => Ok(SameSite::Strict),
+        "lax" => Ok(SameSite::Lax),
+        "none" => Ok(SameSite::None),
+        _ => anyhow::bail!("Invalid cookie SameSite mode {}, expected strict, lax or none", value),
+    }
+}
+
 /// Gracefully shuts down the session deletion task when the service exits.
 async fn shutdown_signal(deletion_task_abort_handle: AbortHandle) {
     let ctrl_c = async {
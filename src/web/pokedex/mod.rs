@@ -0,0 +1,107 @@
+use axum::Json;
+use axum::response::IntoResponse;
+use axum::extract::{Path, State};
+use serde::Serialize;
+use sqlx::{query, query_as};
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+
+use crate::error::AppError;
+use crate::web::api::ErrorBody;
+use crate::pokedex::{parse_entries, PokedexEntry};
+use crate::web::AppState;
+
+/// A Pokédex definition without its entries.
+#[derive(Debug, Serialize, ToSchema)]
+struct PokedexSummary {
+    id: String,
+    name: String,
+    description: String,
+    num_entries: i32,
+    thumbnail_url: String,
+    spritesheet_url: String,
+    revision: i32,
+    /// Retired Pokédexes can't be added to profiles anymore.
+    retired: bool,
+}
+
+/// A Pokédex definition along with its entries.
+#[derive(Debug, Serialize, ToSchema)]
+struct PokedexDefinition {
+    #[serde(flatten)]
+    summary: PokedexSummary,
+    /// Empty slots of the layout are left out.
+    entries: Vec<PokedexEntry>,
+}
+
+/// Build the router for the Pokédex catalog part of the JSON API.
+/// The routes are relative to the API version.
+pub fn api_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get::pokedexes))
+        .routes(routes!(get::pokedex))
+}
+
+mod get {
+
+    use super::*;
+
+    /// Lists every Pokédex there is.
+    #[utoipa::path(
+        get,
+        path = "/pokedexes",
+        tag = "pokedexes",
+        responses((status = 200, body = [PokedexSummary])),
+    )]
+    pub async fn pokedexes(State(state): State<AppState>) -> Result<impl IntoResponse, AppError> {
+        let pokedexes = query_as!(
+            PokedexSummary,
+            "
+            select id, name, description, num_entries, thumbnail_url, spritesheet_url, revision, retired
+            from pokedex
+            order by name
+            "
+        ).fetch_all(&state.database).await?;
+        Ok(Json(pokedexes))
+    }
+
+    /// Get a single Pokédex along with its entries.
+    #[utoipa::path(
+        get,
+        path = "/pokedexes/{pokedex_id}",
+        tag = "pokedexes",
+        params(("pokedex_id" = String, Path)),
+        responses(
+            (status = 200, body = PokedexDefinition),
+            (status = 404, body = ErrorBody),
+        ),
+    )]
+    pub async fn pokedex(
+        Path(pokedex_id): Path<String>,
+        State(state): State<AppState>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let pokedex = query!(
+            "
+            select id, name, description, num_entries, thumbnail_url, spritesheet_url, revision, retired, entries
+            from pokedex
+            where id = ?
+            ",
+            pokedex_id
+        ).fetch_optional(&state.database).await?;
+        let pokedex = pokedex.ok_or(AppError::NotFound)?;
+        Ok(Json(PokedexDefinition {
+            entries: parse_entries(pokedex.entries)?.into_iter().flatten().collect(),
+            summary: PokedexSummary {
+                id: pokedex.id,
+                name: pokedex.name,
+                description: pokedex.description,
+                num_entries: pokedex.num_entries,
+                thumbnail_url: pokedex.thumbnail_url,
+                spritesheet_url: pokedex.spritesheet_url,
+                revision: pokedex.revision,
+                retired: pokedex.retired,
+            },
+        }))
+    }
+}
@@ -0,0 +1,63 @@
+use axum::extract::{Request, State};
+use axum::middleware::Next;
+use axum::response::Response;
+use time::{Duration, OffsetDateTime};
+use tower_sessions::{Expiry, Session};
+use tracing::debug;
+
+use crate::auth::AuthSession;
+use crate::error::AppError;
+
+/// The session key of the time a login ends, as a unix timestamp.
+const DEADLINE_KEY: &str = "login_deadline";
+
+/// How long logins last, no matter how active the user is.
+/// The inactivity lifetime is handled by the expiry of the session layer.
+#[derive(Copy, Clone, Debug)]
+pub struct SessionPolicy {
+    pub absolute_lifetime: Duration,
+    /// The lifetime of logins with "remember me".
+    pub remember_lifetime: Duration,
+}
+
+impl SessionPolicy {
+    /// Keeps the session of a login with "remember me" for the remember lifetime.
+    /// Unlike other sessions, it survives inactivity and closing the browser.
+    pub async fn remember(&self, session: &Session) -> Result<(), AppError> {
+        let deadline = OffsetDateTime::now_utc() + self.remember_lifetime;
+        session.set_expiry(Some(Expiry::AtDateTime(deadline)));
+        session.insert(DEADLINE_KEY, deadline.unix_timestamp()).await.map_err(anyhow::Error::from)?;
+        Ok(())
+    }
+}
+
+/// Logs out users whose login has outlived its absolute lifetime.
+/// Logins that don't have a deadline yet, e.g., right after signing up, get one.
+/// This has to run inside of the auth layer, but outside of `bearer_auth`,
+/// so API tokens don't end up in a session.
+pub async fn enforce_deadline(
+    State(policy): State<SessionPolicy>,
+    mut request: Request,
+    next: Next,
+) -> Result<Response, AppError> {
+    let session = request.extensions().get::<Session>().cloned();
+    let auth_session = request.extensions_mut().get_mut::<AuthSession>();
+    if let (Some(session), Some(auth_session)) = (session, auth_session) {
+        if let Some(user) = &auth_session.user {
+            let now = OffsetDateTime::now_utc().unix_timestamp();
+            let deadline = session.get::<i64>(DEADLINE_KEY).await.map_err(anyhow::Error::from)?;
+            match deadline {
+                Some(deadline) if deadline <= now => {
+                    debug!("The login of {} has expired.", user.name);
+                    auth_session.logout().await.map_err(anyhow::Error::from)?;
+                }
+                Some(_) => {}
+                None => {
+                    let deadline = now + policy.absolute_lifetime.whole_seconds();
+                    session.insert(DEADLINE_KEY, deadline).await.map_err(anyhow::Error::from)?;
+                }
+            }
+        }
+    }
+    Ok(next.run(request).await)
+}
@@ -0,0 +1,100 @@
+use askama::Template;
+use axum::{
+    http::StatusCode,
+    response::{IntoResponse, Redirect, Html},
+    routing::get,
+    Extension, Form, Router,
+};
+use axum_messages::{Message, Messages};
+use serde::Deserialize;
+use tower_sessions::Session;
+
+use crate::error::AppError;
+use crate::auth::AuthSession;
+use crate::web::csrf::{self, CsrfToken};
+
+/// The signup page HTML template.
+#[derive(Template)]
+#[template(path = "signup.html")]
+struct SignupTemplate {
+    messages: Vec<Message>,
+    /// Has to be sent along with requests that change something.
+    csrf_token: String,
+}
+
+/// The form that is submitted to create a new account.
+#[derive(Debug, Clone, Deserialize)]
+struct SignupForm {
+    username: String,
+    password: String,
+    password_confirmation: String,
+}
+
+/// Build a router for all signup-related routes.
+pub fn router() -> Router<()> {
+    Router::new()
+        .route("/signup", get(get::signup).post(post::signup))
+}
+
+mod get {
+
+    use super::*;
+
+    pub async fn signup(messages: Messages, CsrfToken(csrf_token): CsrfToken) -> Result<impl IntoResponse, AppError> {
+        Ok(Html(SignupTemplate {
+            messages: messages.into_iter().collect(),
+            csrf_token,
+        }.render()?))
+    }
+}
+
+mod post {
+    use tracing::{error, info};
+    use crate::auth::validate_username;
+    use super::*;
+
+    pub async fn signup(
+        mut auth_session: AuthSession,
+        messages: Messages,
+        Extension(session): Extension<Session>,
+        Form(form): Form<SignupForm>,
+    ) -> impl IntoResponse {
+        if let Err(message) = validate_username(&form.username) {
+            messages.error(message);
+            return Redirect::to("/signup").into_response();
+        }
+        if let Err(message) = auth_session.backend.password_policy().check(&form.password) {
+            messages.error(message);
+            return Redirect::to("/signup").into_response();
+        }
+        if form.password != form.password_confirmation {
+            messages.error("The passwords do not match");
+            return Redirect::to("/signup").into_response();
+        }
+
+        let user = match auth_session.backend.create_user(&form.username, form.password).await {
+            Ok(user) => user,
+            Err(AppError::AlreadyExists) => {
+                messages.error(format!("The username {} is already taken", form.username));
+                return Redirect::to("/signup").into_response();
+            }
+            Err(err) => {
+                error!("Failed to create user: {}", err);
+                return err.into_response();
+            }
+        };
+        info!("User {} has signed up.", user.name);
+
+        if auth_session.login(&user).await.is_err() {
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+        // A token planted before the signup must not be valid for the logged-in session.
+        if let Err(err) = csrf::rotate(&session).await {
+            error!("Failed to rotate the CSRF token: {}", err);
+            return StatusCode::INTERNAL_SERVER_ERROR.into_response();
+        }
+
+        messages.success(format!("Welcome to MyDex, {}!", user.name));
+        Redirect::to(format!("/user/{}", user.name).as_str()).into_response()
+    }
+}
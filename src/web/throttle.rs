@@ -0,0 +1,195 @@
+use std::net::IpAddr;
+use std::time::Duration;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_scalar, MySqlPool};
+use tracing::warn;
+
+use crate::error::AppError;
+
+/// Failed logins to an account before it is locked out.
+const FREE_ACCOUNT_FAILURES: i32 = 5;
+
+/// Failed logins from an IP address before it is locked out.
+/// This is higher than for accounts, since many users may share an address.
+const FREE_IP_FAILURES: i32 = 20;
+
+/// The lockout after the first failure beyond the free ones. Every further failure doubles it.
+const BASE_LOCKOUT: Duration = Duration::from_secs(5);
+
+const MAX_LOCKOUT: Duration = Duration::from_secs(60 * 60);
+
+/// The length of the `login_throttle.subject` column.
+const MAX_SUBJECT_LENGTH: usize = 64;
+
+/// Failures are forgotten once there was no further failure for this long.
+pub const FAILURE_RESET: Duration = Duration::from_secs(24 * 60 * 60);
+
+/// What failed logins are counted for.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+pub enum ThrottleKind {
+    /// Guessing the password of a single account.
+    Account,
+    /// Guessing the passwords of many accounts from one address.
+    Ip,
+}
+
+impl ThrottleKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            ThrottleKind::Account => "account",
+            ThrottleKind::Ip => "ip",
+        }
+    }
+
+    fn free_failures(&self) -> i32 {
+        match self {
+            ThrottleKind::Account => FREE_ACCOUNT_FAILURES,
+            ThrottleKind::Ip => FREE_IP_FAILURES,
+        }
+    }
+}
+
+/// A login attempt, identified by the account it is for and the address it comes from.
+pub struct LoginAttempt {
+    /// Usernames are compared case-insensitively, so the account is tracked in lowercase.
+    account: String,
+    ip: String,
+}
+
+impl LoginAttempt {
+    pub fn new(username: &str, ip: IpAddr) -> Self {
+        LoginAttempt {
+            // Longer names can't exist, so they may share the counter of their prefix.
+            account: username.to_lowercase().chars().take(MAX_SUBJECT_LENGTH).collect(),
+            ip: ip.to_string(),
+        }
+    }
+
+    fn subjects(&self) -> [(ThrottleKind, &str); 2] {
+        [(ThrottleKind::Account, &self.account), (ThrottleKind::Ip, &self.ip)]
+    }
+}
+
+/// How long to lock out a subject after its `failures`th failed login, if at all.
+fn lockout_duration(kind: ThrottleKind, failures: i32) -> Option<Duration> {
+    let excess = failures - kind.free_failures();
+    if excess <= 0 {
+        return None;
+    }
+    // The lockout is capped long before the shift could overflow.
+    let factor = 1u32.checked_shl((excess - 1) as u32).unwrap_or(u32::MAX);
+    Some(BASE_LOCKOUT.saturating_mul(factor).min(MAX_LOCKOUT))
+}
+
+/// Checks if the account or the address of an attempt is locked out.
+/// Locked out attempts are rejected without checking the password.
+pub async fn is_locked(db: &MySqlPool, attempt: &LoginAttempt) -> Result<bool, AppError> {
+    let locked = query_scalar!(
+        "
+        select count(*) from login_throttle
+        where ((kind = 'account' and subject = ?) or (kind = 'ip' and subject = ?))
+            and locked_until > current_timestamp
+        ",
+        attempt.account,
+        attempt.ip
+    ).fetch_one(db).await?;
+    Ok(locked > 0)
+}
+
+/// Counts a failed login for the account and the address of an attempt
+/// and locks them out once they have used up their free failures.
+pub async fn record_failure(db: &MySqlPool, attempt: &LoginAttempt) -> Result<(), AppError> {
+    for (kind, subject) in attempt.subjects() {
+        // The counter starts over if the last failure was long ago.
+        query!(
+            "
+            insert into login_throttle (kind, subject, failures) values (?, ?, 1)
+            on duplicate key update
+                failures = if(last_failure < current_timestamp - interval ? second, 1, failures + 1),
+                last_failure = current_timestamp
+            ",
+            kind,
+            subject,
+            FAILURE_RESET.as_secs()
+        ).execute(db).await?;
+
+        let failures = query_scalar!(
+            "select failures from login_throttle where kind = ? and subject = ?",
+            kind,
+            subject
+        ).fetch_one(db).await?;
+        if let Some(lockout) = lockout_duration(kind, failures) {
+            query!(
+                "update login_throttle set locked_until = current_timestamp + interval ? second where kind = ? and subject = ?",
+                lockout.as_secs(),
+                kind,
+                subject
+            ).execute(db).await?;
+            warn!(
+                "Locked out {} {} for {} seconds after {} failed logins.",
+                kind.name(), subject, lockout.as_secs(), failures
+            );
+        }
+    }
+
+    // Forgotten failures don't need to be kept around.
+    query!(
+        "
+        delete from login_throttle
+        where last_failure < current_timestamp - interval ? second
+            and (locked_until is null or locked_until < current_timestamp)
+        ",
+        FAILURE_RESET.as_secs()
+    ).execute(db).await?;
+    Ok(())
+}
+
+/// Resets the failures of the account after a successful login.
+/// The failures of the address are kept, otherwise logging into an own account
+/// would reset the limit for guessing the passwords of others.
+pub async fn record_success(db: &MySqlPool, attempt: &LoginAttempt) -> Result<(), AppError> {
+    query!(
+        "delete from login_throttle where kind = 'account' and subject = ?",
+        attempt.account
+    ).execute(db).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn free_failures_are_not_locked_out() {
+        for failures in 0..=FREE_ACCOUNT_FAILURES {
+            assert_eq!(lockout_duration(ThrottleKind::Account, failures), None);
+        }
+        for failures in 0..=FREE_IP_FAILURES {
+            assert_eq!(lockout_duration(ThrottleKind::Ip, failures), None);
+        }
+    }
+
+    #[test]
+    fn lockouts_double_with_every_failure() {
+        let first = FREE_ACCOUNT_FAILURES + 1;
+        assert_eq!(lockout_duration(ThrottleKind::Account, first), Some(BASE_LOCKOUT));
+        assert_eq!(lockout_duration(ThrottleKind::Account, first + 1), Some(BASE_LOCKOUT * 2));
+        assert_eq!(lockout_duration(ThrottleKind::Account, first + 2), Some(BASE_LOCKOUT * 4));
+        assert_eq!(lockout_duration(ThrottleKind::Ip, FREE_IP_FAILURES + 1), Some(BASE_LOCKOUT));
+    }
+
+    #[test]
+    fn lockouts_are_capped() {
+        assert_eq!(lockout_duration(ThrottleKind::Account, FREE_ACCOUNT_FAILURES + 20), Some(MAX_LOCKOUT));
+        assert_eq!(lockout_duration(ThrottleKind::Account, i32::MAX), Some(MAX_LOCKOUT));
+    }
+
+    #[test]
+    fn long_usernames_fit_the_subject_column() {
+        let attempt = LoginAttempt::new(&"Ä".repeat(100), IpAddr::from([127, 0, 0, 1]));
+        assert_eq!(attempt.account.chars().count(), MAX_SUBJECT_LENGTH);
+        assert_eq!(attempt.account, "ä".repeat(MAX_SUBJECT_LENGTH));
+    }
+}
@@ -0,0 +1,44 @@
+use axum::extract::Request;
+use axum::middleware::Next;
+use axum::response::Response;
+use http::header;
+use tracing::debug;
+
+use crate::auth::AuthSession;
+use crate::error::AppError;
+
+/// Authenticates requests that carry an API token in a Bearer authorization header.
+/// The user of the token replaces the user of the session, so handlers and permission
+/// checks work the same for tokens and sessions. Requests outside the scope of the
+/// token are rejected. The scope is added to the request extensions.
+/// This has to run inside of the auth layer, which provides the auth session.
+pub async fn bearer_auth(mut request: Request, next: Next) -> Result<Response, AppError> {
+    let token = request.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map(|token| token.trim().to_string());
+    let Some(token) = token else {
+        return Ok(next.run(request).await);
+    };
+
+    let backend = request.extensions()
+        .get::<AuthSession>()
+        .map(|auth_session| auth_session.backend.clone())
+        .ok_or_else(|| anyhow::anyhow!("The auth session is missing. Is the auth layer set up?"))?;
+    let (user, scope) = backend.authenticate_api_token(&token).await?.ok_or(AppError::Unauthorized)?;
+    if !scope.allows(request.method(), request.uri().path()) {
+        debug!(
+            "Rejected {} {} with an API token of {} with scope {}.",
+            request.method(), request.uri().path(), user.name, scope.name()
+        );
+        return Err(AppError::Unauthorized);
+    }
+
+    if let Some(auth_session) = request.extensions_mut().get_mut::<AuthSession>() {
+        auth_session.user = Some(user);
+    }
+    request.extensions_mut().insert(scope);
+
+    Ok(next.run(request).await)
+}
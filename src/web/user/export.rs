@@ -0,0 +1,465 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use axum::Router;
+use axum::routing::{get, post};
+use axum::response::IntoResponse;
+use axum::extract::{Path, State};
+use axum::{Extension, Json};
+use http::{header, HeaderMap};
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_scalar, MySqlPool};
+use tracing::info;
+
+use crate::error::AppError;
+use crate::web::AppState;
+use crate::auth::{AuthSession, Permission};
+use crate::pokedex::{parse_entries, PokedexEntries, PokedexEntry};
+use crate::web::user::{audit_profile_edit, authorize_profile_edit_with_all, find_visible_user, is_visible, Visibility};
+use crate::web::audit::{AuditAction, AuditLog};
+
+/// All Pokédexes and progress of a user.
+#[derive(Debug, Serialize, Deserialize)]
+struct Export {
+    username: String,
+    pokedexes: Vec<ExportedPokedex>,
+}
+
+/// A Pokédex that is on the user's profile or that the user has progress in.
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedPokedex {
+    id: String,
+    name: String,
+    /// The revision of the definition the entries were exported from.
+    revision: i32,
+    /// Progress of removed Pokédexes is kept, so it is exported along with the rest.
+    on_profile: bool,
+    entries: Vec<ExportedEntry>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct ExportedEntry {
+    id: i32,
+    #[serde(default)]
+    key: Option<String>,
+    name: String,
+    #[serde(default)]
+    form: Option<String>,
+    #[serde(default)]
+    shiny: bool,
+    #[serde(default)]
+    gmax: bool,
+    collected: bool,
+    /// The Pokédex the entry was marked from through a species link.
+    #[serde(default)]
+    source_pokedex_id: Option<String>,
+}
+
+/// A line of the CSV export. This is the JSON export flattened to one row per entry.
+#[derive(Debug, Serialize, Deserialize)]
+struct CsvRow {
+    pokedex_id: String,
+    pokedex_name: String,
+    revision: i32,
+    on_profile: bool,
+    entry_id: i32,
+    entry_key: Option<String>,
+    entry_name: String,
+    form: Option<String>,
+    shiny: bool,
+    gmax: bool,
+    collected: bool,
+    source_pokedex_id: Option<String>,
+}
+
+/// An entry of an import that does not match any entry of the current definition.
+#[derive(Debug, Serialize)]
+struct UnknownEntry {
+    pokedex_id: String,
+    entry_id: i32,
+    name: String,
+}
+
+/// The outcome of an import.
+/// Unknown Pokédexes and entries are reported, everything else is imported.
+#[derive(Debug, Serialize)]
+struct ImportReport {
+    /// Pokédexes that were added to the profile.
+    added_pokedexes: Vec<String>,
+    /// The number of entries that were newly marked as collected.
+    marked_entries: usize,
+    /// Pokédexes that don't exist or are retired.
+    unknown_pokedexes: Vec<String>,
+    unknown_entries: Vec<UnknownEntry>,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/user/{username}/export.json", get(get::export_json))
+        .route("/user/{username}/export.csv", get(get::export_csv))
+        .route("/user/{username}/import", post(post::import))
+}
+
+/// Collects the Pokédexes and progress of a user that the visitor may see.
+async fn load_export(db: &MySqlPool, auth_session: &AuthSession, username: String) -> Result<Export, AppError> {
+    let (user_id, visibility) = find_visible_user(db, auth_session, &username).await?;
+
+    let pokedexes = query!(
+        "
+        select pokedex.id, pokedex.name, pokedex.revision, pokedex.entries
+        from pokedex
+        where
+            pokedex.id in (select pokedex_id from user_pokedex where user_id = ?) or
+            pokedex.id in (select pokedex_id from user_pokedex_progress where user_id = ?)
+        order by pokedex.name
+        ",
+        user_id,
+        user_id
+    ).fetch_all(db).await?;
+    // Maps the Pokédexes on the profile to their visibility override.
+    let on_profile: HashMap<String, Option<Visibility>> = query!(
+        "select pokedex_id, visibility as 'visibility: Visibility' from user_pokedex where user_id = ?",
+        user_id
+    ).fetch_all(db).await?
+        .into_iter()
+        .map(|row| (row.pokedex_id, row.visibility))
+        .collect();
+
+    // Maps each collected entry to the Pokédex it was marked from, if any.
+    let mut progress: HashMap<(String, i32), Option<String>> = HashMap::new();
+    for row in query!(
+        "select pokedex_id, entry_id, source_pokedex_id from user_pokedex_progress where user_id = ?",
+        user_id
+    ).fetch_all(db).await? {
+        progress.insert((row.pokedex_id, row.entry_id), row.source_pokedex_id);
+    }
+
+    let mut export = Export { username, pokedexes: vec![] };
+    for pokedex in pokedexes {
+        let pokedex_visibility = on_profile.get(&pokedex.id).copied().flatten().unwrap_or(visibility);
+        if !is_visible(auth_session, user_id, pokedex_visibility).await? {
+            continue;
+        }
+        let entries = parse_entries(pokedex.entries)?
+            .into_iter()
+            .flatten()
+            .map(|entry| {
+                let progress = progress.get(&(pokedex.id.clone(), entry.id));
+                ExportedEntry {
+                    id: entry.id,
+                    key: entry.key,
+                    name: entry.name,
+                    form: entry.form,
+                    shiny: entry.shiny,
+                    gmax: entry.gmax,
+                    collected: progress.is_some(),
+                    source_pokedex_id: progress.cloned().flatten(),
+                }
+            })
+            .collect();
+        export.pokedexes.push(ExportedPokedex {
+            id: pokedex.id,
+            name: pokedex.name,
+            revision: pokedex.revision,
+            on_profile: on_profile.contains_key(&pokedex.id),
+            entries,
+        });
+    }
+    Ok(export)
+}
+
+/// Finds the entry of the current definition that an imported entry refers to.
+/// Entry IDs change between revisions, so the key is preferred. Without a key,
+/// the ID only counts if the name still matches.
+fn resolve_entry<'a>(entries: &'a PokedexEntries, imported: &ExportedEntry) -> Option<&'a PokedexEntry> {
+    let mut entries = entries.iter().flatten();
+    if let Some(key) = &imported.key {
+        if let Some(entry) = entries.clone().find(|entry| entry.key.as_ref() == Some(key)) {
+            return Some(entry);
+        }
+    }
+    entries.find(|entry| entry.id == imported.id && entry.name == imported.name)
+}
+
+/// Parses an import in the format of either export, depending on the content type.
+fn parse_import(headers: &HeaderMap, body: &str) -> Result<Vec<ExportedPokedex>, AppError> {
+    let is_csv = headers.get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.starts_with("text/csv"));
+    if !is_csv {
+        let export: Export = serde_json::from_str(body).map_err(|_| AppError::BadRequest)?;
+        return Ok(export.pokedexes);
+    }
+
+    // Group the rows by Pokédex again. Rows of a Pokédex don't have to be next to each other.
+    let mut pokedexes = BTreeMap::new();
+    for row in csv::Reader::from_reader(body.as_bytes()).deserialize() {
+        let row: CsvRow = row.map_err(|_| AppError::BadRequest)?;
+        let pokedex = pokedexes.entry(row.pokedex_id.clone()).or_insert_with(|| ExportedPokedex {
+            id: row.pokedex_id,
+            name: row.pokedex_name,
+            revision: row.revision,
+            on_profile: row.on_profile,
+            entries: vec![],
+        });
+        pokedex.entries.push(ExportedEntry {
+            id: row.entry_id,
+            key: row.entry_key,
+            name: row.entry_name,
+            form: row.form,
+            shiny: row.shiny,
+            gmax: row.gmax,
+            collected: row.collected,
+            source_pokedex_id: row.source_pokedex_id,
+        });
+    }
+    Ok(pokedexes.into_values().collect())
+}
+
+mod get {
+
+    use super::*;
+
+    /// Exports the Pokédexes and progress of a user as JSON.
+    pub async fn export_json(
+        Path(username): Path<String>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+    ) -> Result<impl IntoResponse, AppError> {
+        let disposition = format!("attachment; filename=\"{}.json\"", username);
+        let export = load_export(&state.database, &auth_session, username).await?;
+        Ok(([(header::CONTENT_DISPOSITION, disposition)], Json(export)))
+    }
+
+    /// Exports the Pokédexes and progress of a user as CSV with one row per entry.
+    pub async fn export_csv(
+        Path(username): Path<String>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+    ) -> Result<impl IntoResponse, AppError> {
+        let disposition = format!("attachment; filename=\"{}.csv\"", username);
+        let export = load_export(&state.database, &auth_session, username).await?;
+
+        let mut writer = csv::Writer::from_writer(vec![]);
+        for pokedex in export.pokedexes {
+            for entry in pokedex.entries {
+                writer.serialize(CsvRow {
+                    pokedex_id: pokedex.id.clone(),
+                    pokedex_name: pokedex.name.clone(),
+                    revision: pokedex.revision,
+                    on_profile: pokedex.on_profile,
+                    entry_id: entry.id,
+                    entry_key: entry.key,
+                    entry_name: entry.name,
+                    form: entry.form,
+                    shiny: entry.shiny,
+                    gmax: entry.gmax,
+                    collected: entry.collected,
+                    source_pokedex_id: entry.source_pokedex_id,
+                }).map_err(anyhow::Error::from)?;
+            }
+        }
+        let csv = writer.into_inner().map_err(|err| anyhow::anyhow!("Failed to write CSV export: {}", err))?;
+
+        Ok((
+            [
+                (header::CONTENT_TYPE, "text/csv; charset=utf-8".to_string()),
+                (header::CONTENT_DISPOSITION, disposition),
+            ],
+            csv,
+        ))
+    }
+}
+
+mod post {
+
+    use super::*;
+
+    /// Imports an export into a user's profile.
+    /// This only adds Pokédexes and marks entries, nothing is removed or unmarked.
+    /// Entries are matched against the current definitions. Unknown entries are reported.
+    /// Either everything that is known is imported or nothing.
+    pub async fn import(
+        Path(username): Path<String>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+        headers: HeaderMap,
+        body: String,
+    ) -> Result<impl IntoResponse, AppError> {
+
+        // Check user auth. An import adds pokedexes and progress alike.
+        let user = authorize_profile_edit_with_all(
+            &auth_session,
+            &username,
+            &[Permission::AddPokedexToOtherProfiles, Permission::EditPokedexProgressOfOtherProfiles],
+        ).await?;
+
+        let imported = parse_import(&headers, &body)?;
+
+        let user_id = query_scalar!("select user_id from user where name = ?", username)
+            .fetch_optional(&state.database).await?;
+        let user_id = user_id.ok_or(AppError::NotFound)?;
+
+        let mut report = ImportReport {
+            added_pokedexes: vec![],
+            marked_entries: 0,
+            unknown_pokedexes: vec![],
+            unknown_entries: vec![],
+        };
+        let mut tx = state.database.begin().await?;
+        let mut known = vec![];
+        for pokedex in imported {
+            let definition = query!("select entries, retired from pokedex where id = ?", pokedex.id)
+                .fetch_optional(&mut *tx).await?;
+            let entries = match definition {
+                Some(definition) if !definition.retired => parse_entries(definition.entries)?,
+                _ => {
+                    report.unknown_pokedexes.push(pokedex.id);
+                    continue;
+                }
+            };
+
+            if pokedex.on_profile {
+                let is_on_profile = query_scalar!(
+                    "select count(*) from user_pokedex where user_id = ? and pokedex_id = ?",
+                    user_id,
+                    pokedex.id
+                ).fetch_one(&mut *tx).await? > 0;
+                if !is_on_profile {
+                    query!("insert into user_pokedex (user_id, pokedex_id) values (?, ?)", user_id, pokedex.id)
+                        .execute(&mut *tx).await?;
+                    report.added_pokedexes.push(pokedex.id.clone());
+                }
+            }
+            known.push((pokedex, entries));
+        }
+
+        // Species links only mark entries from Pokédexes on the profile, so other sources are dropped.
+        // The profile is loaded after the additions, since an import can add the source as well.
+        let profile: HashSet<String> = query_scalar!("select pokedex_id from user_pokedex where user_id = ?", user_id)
+            .fetch_all(&mut *tx).await?
+            .into_iter()
+            .collect();
+        for (pokedex, entries) in known {
+            for imported_entry in pokedex.entries.iter().filter(|entry| entry.collected) {
+                let Some(entry) = resolve_entry(&entries, imported_entry) else {
+                    report.unknown_entries.push(UnknownEntry {
+                        pokedex_id: pokedex.id.clone(),
+                        entry_id: imported_entry.id,
+                        name: imported_entry.name.clone(),
+                    });
+                    continue;
+                };
+                let result = query!(
+                    "
+                    insert ignore into user_pokedex_progress (user_id, pokedex_id, entry_id, source_pokedex_id)
+                    values (?, ?, ?, ?)
+                    ",
+                    user_id,
+                    pokedex.id,
+                    entry.id,
+                    imported_entry.source_pokedex_id.clone().filter(|source| profile.contains(source))
+                ).execute(&mut *tx).await?;
+                report.marked_entries += result.rows_affected() as usize;
+            }
+        }
+        tx.commit().await?;
+
+        info!(
+            "User {} has imported data into {}'s profile: {} pokedexes added, {} entries marked, {} unknown pokedexes, {} unknown entries.",
+            user.name, username, report.added_pokedexes.len(), report.marked_entries,
+            report.unknown_pokedexes.len(), report.unknown_entries.len()
+        );
+        let details = format!(
+            "{} pokedexes added, {} entries marked",
+            report.added_pokedexes.len(), report.marked_entries
+        );
+        audit_profile_edit(&audit, &user, &username, AuditAction::ProgressImport, &details).await;
+        Ok(Json(report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use http::HeaderValue;
+
+    use super::*;
+
+    fn csv_headers() -> HeaderMap {
+        HeaderMap::from_iter([(header::CONTENT_TYPE, HeaderValue::from_static("text/csv; charset=utf-8"))])
+    }
+
+    #[test]
+    fn csv_rows_are_grouped_by_pokedex() {
+        let body = "\
+            pokedex_id,pokedex_name,revision,on_profile,entry_id,entry_key,entry_name,form,shiny,gmax,collected,source_pokedex_id\n\
+            national,National,3,true,1,bulbasaur,Bulbasaur,,false,false,true,\n\
+            galar,Galar,1,false,25,,Pikachu,,false,true,true,national\n\
+            national,National,3,true,25,,Pikachu,Cosplay,true,false,false,\n";
+
+        let pokedexes = parse_import(&csv_headers(), body).unwrap();
+        assert_eq!(pokedexes.len(), 2);
+
+        let galar = &pokedexes[0];
+        assert_eq!((galar.id.as_str(), galar.name.as_str(), galar.revision, galar.on_profile), ("galar", "Galar", 1, false));
+        assert_eq!(galar.entries.len(), 1);
+        assert!(galar.entries[0].gmax);
+        assert_eq!(galar.entries[0].source_pokedex_id.as_deref(), Some("national"));
+
+        let national = &pokedexes[1];
+        assert_eq!((national.id.as_str(), national.revision, national.on_profile), ("national", 3, true));
+        let ids: Vec<i32> = national.entries.iter().map(|entry| entry.id).collect();
+        assert_eq!(ids, vec![1, 25]);
+    }
+
+    #[test]
+    fn empty_csv_fields_are_none() {
+        let body = "\
+            pokedex_id,pokedex_name,revision,on_profile,entry_id,entry_key,entry_name,form,shiny,gmax,collected,source_pokedex_id\n\
+            national,National,3,true,25,,Pikachu,,true,false,true,\n";
+        let pokedexes = parse_import(&csv_headers(), body).unwrap();
+        let entry = &pokedexes[0].entries[0];
+        assert_eq!(entry.key, None);
+        assert_eq!(entry.form, None);
+        assert_eq!(entry.source_pokedex_id, None);
+        assert!(entry.shiny && entry.collected);
+    }
+
+    #[test]
+    fn quoted_csv_fields_are_unquoted() {
+        let body = "\
+            pokedex_id,pokedex_name,revision,on_profile,entry_id,entry_key,entry_name,form,shiny,gmax,collected,source_pokedex_id\n\
+            national,\"National, with a comma\",2,true,6,charizard-mega-x,Charizard,\"Mega \"\"X\"\"\",false,false,true,\n";
+        let pokedexes = parse_import(&csv_headers(), body).unwrap();
+        assert_eq!(pokedexes[0].name, "National, with a comma");
+        let entry = &pokedexes[0].entries[0];
+        assert_eq!(entry.key.as_deref(), Some("charizard-mega-x"));
+        assert_eq!(entry.form.as_deref(), Some("Mega \"X\""));
+    }
+
+    #[test]
+    fn malformed_csv_is_rejected() {
+        let bodies = [
+            // The revision is not a number.
+            "pokedex_id,pokedex_name,revision,on_profile,entry_id,entry_key,entry_name,form,shiny,gmax,collected,source_pokedex_id\n\
+            national,National,three,true,25,,Pikachu,,false,false,true,\n",
+            // Booleans are spelled out.
+            "pokedex_id,pokedex_name,revision,on_profile,entry_id,entry_key,entry_name,form,shiny,gmax,collected,source_pokedex_id\n\
+            national,National,3,yes,25,,Pikachu,,false,false,true,\n",
+            // The row is cut off.
+            "pokedex_id,pokedex_name,revision,on_profile,entry_id,entry_key,entry_name,form,shiny,gmax,collected,source_pokedex_id\n\
+            national,National,3,true,25\n",
+        ];
+        for body in bodies {
+            assert!(matches!(parse_import(&csv_headers(), body), Err(AppError::BadRequest)), "{}", body);
+        }
+    }
+
+    #[test]
+    fn other_content_types_are_json() {
+        let body = r#"{"username": "ash", "pokedexes": [{"id": "national", "name": "National", "revision": 3, "on_profile": true, "entries": [{"id": 25, "name": "Pikachu", "collected": true}]}]}"#;
+        let pokedexes = parse_import(&HeaderMap::new(), body).unwrap();
+        assert_eq!(pokedexes[0].entries[0].name, "Pikachu");
+
+        assert!(matches!(parse_import(&HeaderMap::new(), "pokedex_id,pokedex_name"), Err(AppError::BadRequest)));
+    }
+}
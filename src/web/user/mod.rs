@@ -1,19 +1,28 @@
+mod export;
 mod pokedex;
 mod settings;
 
 use askama::Template;
 use axum::response::{Html, IntoResponse, Redirect};
-use axum::Router;
+use axum::{Json, Router};
 use axum::routing::get;
 use axum::extract::{Path, State};
-use sqlx::{query_as, query_scalar, FromRow};
+use axum_login::AuthzBackend;
+use serde::{Deserialize, Serialize};
+use sqlx::{query, query_as, query_scalar, FromRow, MySqlExecutor, MySqlPool};
+use utoipa::ToSchema;
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
 
 use crate::error::AppError;
+use crate::web::api::ErrorBody;
 use crate::web::AppState;
-use crate::auth::AuthSession;
+use crate::web::audit::{AuditAction, AuditLog};
+use crate::web::csrf::CsrfToken;
+use crate::auth::{AuthSession, Permission, User};
 
 /// Information about a Pokédex a user has added to their profile.
-#[derive(sqlx::FromRow, Debug)]
+#[derive(sqlx::FromRow, Debug, Serialize, ToSchema)]
 struct PokedexProgress {
     id: String,
     name: String,
@@ -21,6 +30,12 @@ struct PokedexProgress {
     num_entries: i32,
     collected: i64,
     thumbnail_url: String,
+    /// Retired Pokédexes are read-only.
+    retired: bool,
+    /// The visibility override of the Pokédex, if any.
+    /// This is the owner's business, so it is not part of the API.
+    #[serde(skip)]
+    visibility: Option<Visibility>,
 }
 
 /// The description of a Pokédex the user has not yet added to their profile.
@@ -33,6 +48,13 @@ struct PokedexDescription {
     thumbnail_url: String,
 }
 
+/// A user as returned by the API.
+#[derive(Debug, Serialize, ToSchema)]
+struct UserView {
+    name: String,
+    pokedexes: Vec<PokedexProgress>,
+}
+
 #[derive(Template)]
 #[template(path = "profile.html")]
 struct ProfileTemplate {
@@ -40,6 +62,145 @@ struct ProfileTemplate {
     own_pokedexes: Vec<PokedexProgress>,
     other_pokedexes: Vec<PokedexDescription>,
     is_own_profile: bool,
+    /// Has to be sent along with requests that change something.
+    csrf_token: String,
+}
+
+/// How marking an entry affects entries of the same species in the user's other Pokédexes.
+/// Only entries without a form that are not shiny are linked.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, sqlx::Type, ToSchema)]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+enum SpeciesLink {
+    /// Entries are only marked in the Pokédex they belong to.
+    Off,
+    /// The user is asked whether to mark the linked entries as well.
+    Offer,
+    /// Linked entries are marked right away.
+    Auto,
+}
+
+impl SpeciesLink {
+    fn name(&self) -> &'static str {
+        match self {
+            SpeciesLink::Off => "off",
+            SpeciesLink::Offer => "offer",
+            SpeciesLink::Auto => "auto",
+        }
+    }
+}
+
+/// Who may see the profile of a user or a single Pokédex on it.
+/// The owner and holders of `ViewHiddenProfiles` can always see everything.
+#[derive(Copy, Clone, Debug, Eq, PartialEq, Serialize, Deserialize, sqlx::Type)]
+#[sqlx(rename_all = "snake_case")]
+#[serde(rename_all = "snake_case")]
+enum Visibility {
+    Public,
+    LoggedIn,
+    Private,
+}
+
+impl Visibility {
+    const ALL: [Visibility; 3] = [Visibility::Public, Visibility::LoggedIn, Visibility::Private];
+
+    fn name(&self) -> &'static str {
+        match self {
+            Visibility::Public => "public",
+            Visibility::LoggedIn => "logged_in",
+            Visibility::Private => "private",
+        }
+    }
+
+    fn from_name(name: &str) -> Option<Visibility> {
+        Visibility::ALL.into_iter().find(|visibility| visibility.name() == name)
+    }
+}
+
+/// Checks if the visitor may see something of the user `owner_id` with the given visibility.
+async fn is_visible(auth_session: &AuthSession, owner_id: i32, visibility: Visibility) -> Result<bool, AppError> {
+    match (&auth_session.user, visibility) {
+        (_, Visibility::Public) => Ok(true),
+        (None, _) => Ok(false),
+        (Some(user), _) if user.user_id == owner_id => Ok(true),
+        (Some(_), Visibility::LoggedIn) => Ok(true),
+        (Some(user), Visibility::Private) => Ok(auth_session.backend.has_perm(user, Permission::ViewHiddenProfiles).await?),
+    }
+}
+
+/// Looks up a user the visitor may see.
+/// Hidden profiles are reported as `AppError::NotFound`, so their existence isn't revealed.
+/// Returns the ID of the user along with the visibility of their profile.
+async fn find_visible_user(
+    db: &MySqlPool,
+    auth_session: &AuthSession,
+    username: &str,
+) -> Result<(i32, Visibility), AppError> {
+    let user = query!(
+        "select user_id, visibility as 'visibility: Visibility' from user where name = ?",
+        username
+    ).fetch_optional(db).await?;
+    let user = user.ok_or(AppError::NotFound)?;
+    if !is_visible(auth_session, user.user_id, user.visibility).await? {
+        return Err(AppError::NotFound);
+    }
+    Ok((user.user_id, user.visibility))
+}
+
+/// Loads the Pokédexes on the profile of a user along with the user's progress in them.
+/// Pokédexes that are hidden from the visitor are left out.
+async fn load_visible_pokedexes(
+    db: &MySqlPool,
+    auth_session: &AuthSession,
+    user_id: i32,
+    visibility: Visibility,
+) -> Result<Vec<PokedexProgress>, AppError> {
+    let own_pokedexes = query_as!(
+        PokedexProgress,
+        "
+        select
+            pokedex.id,
+            pokedex.name,
+            pokedex.description,
+            pokedex.thumbnail_url,
+            pokedex.retired,
+            user_pokedex.visibility as 'visibility: Visibility',
+            num_entries,
+            coalesce(counts.collected, 0) as collected
+        from
+            user_pokedex
+            left join
+            (
+                select user_pokedex_progress.pokedex_id, count(*) as collected
+                from user_pokedex_progress
+                where user_pokedex_progress.user_id = ?
+                group by user_pokedex_progress.pokedex_id
+            ) counts
+            on counts.pokedex_id = user_pokedex.pokedex_id,
+            pokedex
+        where user_id = ? and user_pokedex.pokedex_id = pokedex.id
+        ",
+        user_id,
+        user_id
+    ).fetch_all(db).await?;
+
+    // Pokédexes may be hidden from the visitor even if the profile is not.
+    let mut visible_pokedexes = vec![];
+    for pokedex in own_pokedexes {
+        if is_visible(auth_session, user_id, pokedex.visibility.unwrap_or(visibility)).await? {
+            visible_pokedexes.push(pokedex);
+        }
+    }
+    Ok(visible_pokedexes)
+}
+
+/// Loads the species link setting of a user.
+async fn load_species_link(db: impl MySqlExecutor<'_>, user_id: i32) -> Result<SpeciesLink, AppError> {
+    let species_link = query_scalar!(
+        "select species_link as 'species_link: SpeciesLink' from user where user_id = ?",
+        user_id
+    ).fetch_optional(db).await?;
+    species_link.ok_or(AppError::NotFound)
 }
 
 pub fn router() -> Router<AppState> {
@@ -47,6 +208,62 @@ pub fn router() -> Router<AppState> {
         .route("/user/{username}", get(get::profile))
         .route("/user", get(get::redirect_to_profile))
         .merge(pokedex::router())
+        .merge(settings::router())
+        .merge(export::router())
+}
+
+/// Build the router for the user part of the JSON API.
+/// The routes are relative to the API version.
+pub fn api_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get::user_json))
+        .merge(pokedex::api_router())
+}
+
+/// Makes sure the logged-in user may edit the profile of `username`.
+/// Everyone may edit their own profile, editing someone else's requires `permission`.
+/// Returns the logged-in user on success.
+async fn authorize_profile_edit(
+    auth_session: &AuthSession,
+    username: &str,
+    permission: Permission,
+) -> Result<User, AppError> {
+    authorize_profile_edit_with_all(auth_session, username, &[permission]).await
+}
+
+/// Like `authorize_profile_edit`, but editing someone else's profile requires all of `permissions`.
+async fn authorize_profile_edit_with_all(
+    auth_session: &AuthSession,
+    username: &str,
+    permissions: &[Permission],
+) -> Result<User, AppError> {
+    let user = match &auth_session.user {
+        None => return Err(AppError::Unauthorized),
+        Some(user) => user.clone(),
+    };
+    if user.name != username {
+        let granted = auth_session.backend.get_all_permissions(&user).await?;
+        if !permissions.iter().all(|permission| granted.contains(permission)) {
+            // A user can't edit the profile of another user unless they have the
+            // required permissions.
+            return Err(AppError::Unauthorized);
+        }
+    }
+    Ok(user)
+}
+
+/// Records an edit of the profile of `username` by `user` in the audit log.
+/// Only edits of other users' profiles are recorded, since only those rely on a permission.
+async fn audit_profile_edit(
+    audit: &AuditLog,
+    user: &User,
+    username: &str,
+    action: AuditAction,
+    details: &str,
+) {
+    if user.name != username {
+        audit.record(action, &user.name, Some(username), Some(details)).await;
+    }
 }
 
 mod get {
@@ -65,46 +282,20 @@ mod get {
         Path(username): Path<String>,
         State(state): State<AppState>,
         auth_session: AuthSession,
+        CsrfToken(csrf_token): CsrfToken,
     ) -> Result<impl IntoResponse, AppError> {
         
-        // Check if the user exists and get their user ID.
-        let user_id = query_scalar!("select user_id from user where name like ?", username)
-            .fetch_optional(&state.database).await?;
-        let user_id = user_id.ok_or(AppError::NotFound)?;
+        // Check if the user exists, the visitor may see them and get their user ID.
+        let (user_id, visibility) = find_visible_user(&state.database, &auth_session, &username).await?;
         
         // If the user is on their own profile, they may edit it.
-        let is_own_profile = match auth_session.user {
+        let is_own_profile = match &auth_session.user {
             None => false,
             Some(user) => user.user_id == user_id,
         };
         
         // Query Pokédex progress of the user to show on their profile.
-        let own_pokedexes = query_as!(
-            PokedexProgress,
-            "
-            select
-                pokedex.id,
-                pokedex.name,
-                pokedex.description,
-                pokedex.thumbnail_url,
-                num_entries,
-                coalesce(counts.collected, 0) as collected
-            from
-                user_pokedex
-                left join
-                (
-                    select user_pokedex_progress.pokedex_id, count(*) as collected
-                    from user_pokedex_progress
-                    where user_pokedex_progress.user_id = ?
-                    group by user_pokedex_progress.pokedex_id
-                ) counts
-                on counts.pokedex_id = user_pokedex.pokedex_id,
-                pokedex
-            where user_id = ? and user_pokedex.pokedex_id = pokedex.id
-            ",
-            user_id,
-            user_id
-        ).fetch_all(&state.database).await?;
+        let own_pokedexes = load_visible_pokedexes(&state.database, &auth_session, user_id, visibility).await?;
         
         let other_pokedexes = if is_own_profile {
             // Query list of pokedexes the user does not have
@@ -113,7 +304,7 @@ mod get {
                 "
                 select pokedex.id, pokedex.name, pokedex.description, pokedex.num_entries, pokedex.thumbnail_url
                 from pokedex
-                where pokedex.id not in (
+                where not pokedex.retired and pokedex.id not in (
                     select user_pokedex.pokedex_id
                     from user, user_pokedex
                     where user.name like ? and user.user_id = user_pokedex.user_id
@@ -130,6 +321,28 @@ mod get {
             own_pokedexes,
             other_pokedexes,
             is_own_profile,
+            csrf_token,
         }.render()?))
     }
+
+    /// Get a user and the progress in the Pokédexes on their profile as JSON.
+    #[utoipa::path(
+        get,
+        path = "/users/{username}",
+        tag = "users",
+        params(("username" = String, Path)),
+        responses(
+            (status = 200, body = UserView),
+            (status = 404, description = "Unknown or hidden user", body = ErrorBody),
+        ),
+    )]
+    pub async fn user_json(
+        Path(username): Path<String>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+    ) -> Result<impl IntoResponse, AppError> {
+        let (user_id, visibility) = find_visible_user(&state.database, &auth_session, &username).await?;
+        let pokedexes = load_visible_pokedexes(&state.database, &auth_session, user_id, visibility).await?;
+        Ok(Json(UserView { name: username, pokedexes }))
+    }
 }
\ No newline at end of file
@@ -1,44 +1,513 @@
-use axum::Router;
-use axum::routing::{put, get};
+use std::collections::HashSet;
+use askama::Template;
+use axum::{Extension, Router};
+use axum::routing::{put, get, delete, post};
+use serde::{Deserialize, Serialize};
+use utoipa::{IntoParams, ToSchema};
+use utoipa_axum::router::OpenApiRouter;
+use utoipa_axum::routes;
+use axum_login::AuthzBackend;
+use sqlx::{query, query_scalar, MySqlConnection, MySqlPool};
 use crate::web::AppState;
 use axum::response::IntoResponse;
 use crate::error::AppError;
+use crate::web::api::ErrorBody;
+use crate::web::audit::{AuditAction, AuditLog};
+use crate::web::csrf::CsrfToken;
+use crate::auth::{AuthSession, Permission};
+use crate::pokedex::{parse_entries, Gender, PokedexEntries, PokedexEntry};
+use crate::web::user::{is_visible, SpeciesLink, Visibility};
+
+/// How many sprites are placed next to each other in a row of a spritesheet.
+/// This has to match `SPRITESHEET_WIDTH` in `scripts/compile_data.py`.
+const SPRITESHEET_WIDTH: i32 = 64;
+
+/// The width and height of a single sprite in a spritesheet in pixels.
+const SPRITE_SIZE: i32 = 64;
+
+/// An entry as shown on the progress page.
+#[derive(Debug)]
+struct EntryView {
+    id: i32,
+    name: String,
+    form: Option<String>,
+    shiny: bool,
+    gmax: bool,
+    /// Horizontal offset of the sprite in the spritesheet in pixels.
+    sprite_x: i32,
+    /// Vertical offset of the sprite in the spritesheet in pixels.
+    sprite_y: i32,
+    collected: bool,
+}
+
+#[derive(Template)]
+#[template(path = "pokedex.html")]
+struct PokedexTemplate {
+    username: String,
+    pokedex_id: String,
+    pokedex_name: String,
+    description: String,
+    num_entries: i32,
+    num_collected: usize,
+    spritesheet_url: String,
+    /// The revision of the definition, so users can tell which version they are looking at.
+    revision: i32,
+    retired: bool,
+    sprite_size: i32,
+    /// Empty entries are `None` and only take up a slot in the grid.
+    entries: Vec<Option<EntryView>>,
+    is_own_profile: bool,
+    /// Whether the visitor may mark entries as collected or uncollected.
+    can_edit: bool,
+    /// The filter as given in the query, so the filter form keeps its values.
+    filter: EntryFilterQuery,
+    is_filtered: bool,
+    /// The number of entries that match the filter.
+    num_matching: usize,
+    /// Has to be sent along with requests that change something.
+    csrf_token: String,
+}
+
+/// The filter of the progress page as given in the query string.
+/// Empty values don't filter, so the filter form can be submitted as is.
+#[derive(Debug, Default, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+#[serde(default)]
+struct EntryFilterQuery {
+    /// Case-insensitive part of the entry name.
+    name: String,
+    /// Case-insensitive part of the form name.
+    form: String,
+    /// `true` or `false`
+    shiny: String,
+    /// `true` or `false`
+    gmax: String,
+    /// `male` or `female`
+    gender: String,
+    /// `collected` or `missing`
+    status: String,
+}
+
+/// A parsed filter for the entries on the progress page.
+#[derive(Debug)]
+struct EntryFilter {
+    name: Option<String>,
+    form: Option<String>,
+    shiny: Option<bool>,
+    gmax: Option<bool>,
+    gender: Option<Gender>,
+    collected: Option<bool>,
+}
+
+impl EntryFilter {
+
+    /// Parses the filter from the query string.
+    /// Returns `AppError::BadRequest` for values that are not understood.
+    fn parse(query: &EntryFilterQuery) -> Result<Self, AppError> {
+        fn text(value: &str) -> Option<String> {
+            let value = value.trim();
+            if value.is_empty() { None } else { Some(value.to_lowercase()) }
+        }
+        fn flag(value: &str) -> Result<Option<bool>, AppError> {
+            match value {
+                "" => Ok(None),
+                "true" => Ok(Some(true)),
+                "false" => Ok(Some(false)),
+                _ => Err(AppError::BadRequest),
+            }
+        }
+        Ok(Self {
+            name: text(&query.name),
+            form: text(&query.form),
+            shiny: flag(&query.shiny)?,
+            gmax: flag(&query.gmax)?,
+            gender: match query.gender.as_str() {
+                "" => None,
+                "male" => Some(Gender::Male),
+                "female" => Some(Gender::Female),
+                _ => return Err(AppError::BadRequest),
+            },
+            collected: match query.status.as_str() {
+                "" => None,
+                "collected" => Some(true),
+                "missing" => Some(false),
+                _ => return Err(AppError::BadRequest),
+            },
+        })
+    }
+
+    /// Whether the filter lets every entry through.
+    fn is_empty(&self) -> bool {
+        self.name.is_none()
+            && self.form.is_none()
+            && self.shiny.is_none()
+            && self.gmax.is_none()
+            && self.gender.is_none()
+            && self.collected.is_none()
+    }
+
+    fn matches(&self, entry: &PokedexEntry, collected: bool) -> bool {
+        let contains = |value: Option<&String>, part: &Option<String>| match part {
+            None => true,
+            Some(part) => value.is_some_and(|value| value.to_lowercase().contains(part)),
+        };
+        contains(Some(&entry.name), &self.name)
+            && contains(entry.form.as_ref(), &self.form)
+            && self.shiny.is_none_or(|shiny| entry.shiny == shiny)
+            && self.gmax.is_none_or(|gmax| entry.gmax == gmax)
+            && self.gender.is_none_or(|gender| entry.gender == Some(gender))
+            && self.collected.is_none_or(|wanted| collected == wanted)
+    }
+}
+
+/// A request to mark many entries of a Pokédex at once.
+#[derive(Debug, Deserialize, ToSchema)]
+struct BulkUpdate {
+    /// Entries to mark as collected.
+    #[serde(default)]
+    add: Vec<i32>,
+    /// Entries to mark as not collected.
+    #[serde(default)]
+    remove: Vec<i32>,
+}
+
+/// Describes which entries were actually changed by a bulk update.
+/// Entries that already were in the requested state are not listed.
+#[derive(Debug, Serialize, ToSchema)]
+struct BulkUpdateSummary {
+    added: Vec<i32>,
+    removed: Vec<i32>,
+    /// Entries in other Pokédexes that were marked through the species link of the user.
+    linked: Vec<LinkedEntry>,
+}
+
+/// An entry of the same species in another Pokédex of the user.
+#[derive(Debug, Serialize, ToSchema)]
+struct LinkedEntry {
+    pokedex_id: String,
+    pokedex_name: String,
+    entry_id: i32,
+}
+
+/// Describes how marking an entry affected the user's other Pokédexes.
+#[derive(Debug, Serialize, ToSchema)]
+struct MarkedEntry {
+    species_link: SpeciesLink,
+    /// With `auto`, these entries were marked along with the entry.
+    /// With `offer`, these entries can be marked through the `linked` endpoint of the entry.
+    linked: Vec<LinkedEntry>,
+}
+
+/// Options for removing a Pokédex from a profile.
+#[derive(Debug, Deserialize, IntoParams)]
+#[into_params(parameter_in = Query)]
+struct RemovePokedexOptions {
+    /// Also delete the progress in the Pokédex.
+    /// Otherwise, the progress is kept and restored if the Pokédex is added again.
+    #[serde(default)]
+    purge: bool,
+}
 
 pub fn router() -> Router<AppState> {
     Router::new()
+        .route("/user/{username}/pokedex/{pokedex_id}/entry/{entry_id}/linked", post(post::linked))
         .route("/user/{username}/pokedex/{pokedex_id}", put(put::pokedex))
         .route("/user/{username}/pokedex/{pokedex_id}", get(get::pokedex))
+        .route("/user/{username}/pokedex/{pokedex_id}", delete(delete::pokedex))
+        .route("/user/{username}/pokedex/{pokedex_id}/entry/{entry_id}", put(put::entry))
+        .route("/user/{username}/pokedex/{pokedex_id}/entry/{entry_id}", delete(delete::entry))
+        .route("/user/{username}/pokedex/{pokedex_id}/entries", post(post::entries))
+}
+
+/// Build the router for the Pokédex part of the JSON API.
+/// The routes are relative to the API version, and everything but reading progress
+/// is shared with the web interface.
+pub fn api_router() -> OpenApiRouter<AppState> {
+    OpenApiRouter::new()
+        .routes(routes!(get::pokedex_json, put::pokedex, delete::pokedex))
+        .routes(routes!(post::entries))
+        .routes(routes!(put::entry, delete::entry))
+        .routes(routes!(post::linked))
+}
+
+/// Loads the entries of a Pokédex that `username` has added to their profile,
+/// so that the user's progress in it can be edited.
+/// Returns the ID of the user along with the entries.
+/// If the user does not exist or does not have the Pokédex on their profile,
+/// this returns `AppError::NotFound`.
+/// Retired Pokédexes are read-only, so this returns `AppError::Conflict` for them.
+async fn load_user_pokedex_entries(
+    db: &MySqlPool,
+    username: &str,
+    pokedex_id: &str,
+) -> Result<(i32, PokedexEntries), AppError> {
+    let pokedex = query!(
+        "
+        select user.user_id, pokedex.entries, pokedex.retired
+        from pokedex, user_pokedex, user
+        where
+            pokedex.id = ? and
+            user.name = ? and
+            user_pokedex.pokedex_id = pokedex.id and
+            user_pokedex.user_id = user.user_id
+        ",
+        pokedex_id,
+        username
+    ).fetch_optional(db).await?;
+    let pokedex = pokedex.ok_or(AppError::NotFound)?;
+    if pokedex.retired {
+        return Err(AppError::Conflict);
+    }
+    let entries = parse_entries(pokedex.entries)?;
+    Ok((pokedex.user_id, entries))
+}
+
+/// Checks if an entry with the given ID is part of the Pokédex.
+fn contains_entry(entries: &PokedexEntries, entry_id: i32) -> bool {
+    find_entry(entries, entry_id).is_some()
+}
+
+fn find_entry(entries: &PokedexEntries, entry_id: i32) -> Option<&PokedexEntry> {
+    entries.iter().flatten().find(|entry| entry.id == entry_id)
+}
+
+/// The other Pokédexes on a user's profile, to find entries of the same species in them.
+/// Retired Pokédexes are read-only and therefore left out.
+struct LinkedPokedexes {
+    pokedexes: Vec<(String, String, PokedexEntries)>,
+    collected: HashSet<(String, i32)>,
+}
+
+impl LinkedPokedexes {
+
+    /// Loads every Pokédex of the user except `pokedex_id`.
+    async fn load(db: &mut MySqlConnection, user_id: i32, pokedex_id: &str) -> Result<Self, AppError> {
+        let rows = query!(
+            "
+            select pokedex.id, pokedex.name, pokedex.entries
+            from pokedex, user_pokedex
+            where
+                user_pokedex.user_id = ? and
+                user_pokedex.pokedex_id = pokedex.id and
+                pokedex.id != ? and
+                not pokedex.retired
+            ",
+            user_id,
+            pokedex_id
+        ).fetch_all(&mut *db).await?;
+        let mut pokedexes = vec![];
+        for row in rows {
+            pokedexes.push((row.id, row.name, parse_entries(row.entries)?));
+        }
+        let collected = query!(
+            "select pokedex_id, entry_id from user_pokedex_progress where user_id = ? and pokedex_id != ?",
+            user_id,
+            pokedex_id
+        ).fetch_all(&mut *db).await?
+            .into_iter()
+            .map(|row| (row.pokedex_id, row.entry_id))
+            .collect();
+        Ok(Self { pokedexes, collected })
+    }
+
+    /// Finds the uncollected entries of the species that are not shiny and have no form.
+    fn find(&self, species: &str) -> Vec<LinkedEntry> {
+        let mut linked = vec![];
+        for (pokedex_id, pokedex_name, entries) in &self.pokedexes {
+            for entry in entries.iter().flatten() {
+                let is_linked = entry.species.as_deref() == Some(species)
+                    && entry.is_base_form()
+                    && !self.collected.contains(&(pokedex_id.clone(), entry.id));
+                if is_linked {
+                    linked.push(LinkedEntry {
+                        pokedex_id: pokedex_id.clone(),
+                        pokedex_name: pokedex_name.clone(),
+                        entry_id: entry.id,
+                    });
+                }
+            }
+        }
+        linked
+    }
+}
+
+/// Marks the linked entries as collected and records which Pokédex they were marked from.
+async fn mark_linked_entries(
+    db: &mut MySqlConnection,
+    user_id: i32,
+    source_pokedex_id: &str,
+    linked: &[LinkedEntry],
+) -> Result<(), AppError> {
+    for entry in linked {
+        query!(
+            "
+            insert ignore into user_pokedex_progress (user_id, pokedex_id, entry_id, source_pokedex_id)
+            values (?, ?, ?, ?)
+            ",
+            user_id,
+            entry.pokedex_id,
+            entry.entry_id,
+            source_pokedex_id
+        ).execute(&mut *db).await?;
+    }
+    Ok(())
+}
+
+/// A Pokédex on a user's profile along with the user's progress in it.
+struct UserPokedex {
+    name: String,
+    description: String,
+    num_entries: i32,
+    spritesheet_url: String,
+    revision: i32,
+    retired: bool,
+    entries: PokedexEntries,
+    collected: HashSet<i32>,
+    is_own_profile: bool,
+    /// Whether the visitor may mark entries as collected or uncollected.
+    can_edit: bool,
+}
+
+/// The progress of a user in a Pokédex as returned by the API.
+#[derive(Debug, Serialize, ToSchema)]
+struct UserPokedexView {
+    id: String,
+    name: String,
+    description: String,
+    revision: i32,
+    retired: bool,
+    num_entries: i32,
+    /// The number of collected entries that match the filter.
+    num_collected: usize,
+    can_edit: bool,
+    entries: Vec<EntryProgress>,
+}
+
+#[derive(Debug, Serialize, ToSchema)]
+struct EntryProgress {
+    #[serde(flatten)]
+    entry: PokedexEntry,
+    collected: bool,
+}
+
+/// Loads a Pokédex on the profile of `username` along with the progress in it.
+/// Hidden profiles and Pokédexes are reported as `AppError::NotFound`.
+async fn load_user_pokedex(
+    db: &MySqlPool,
+    auth_session: &AuthSession,
+    username: &str,
+    pokedex_id: &str,
+) -> Result<UserPokedex, AppError> {
+
+    // Check if the user exists and get their user ID.
+    let user = query!(
+        "select user_id, visibility as 'visibility: Visibility' from user where name = ?",
+        username
+    ).fetch_optional(db).await?;
+    let user = user.ok_or(AppError::NotFound)?;
+    let user_id = user.user_id;
+
+    // Visitors may look at the progress, but only the owner or users with the
+    // required permission may edit it.
+    let (is_own_profile, can_edit) = match &auth_session.user {
+        None => (false, false),
+        Some(user) if user.user_id == user_id => (true, true),
+        Some(user) => (
+            false,
+            auth_session.backend.has_perm(user, Permission::EditPokedexProgressOfOtherProfiles).await?,
+        ),
+    };
+
+    // Only pokedexes the user has added to their profile have a progress page.
+    let pokedex = query!(
+        "
+        select
+            pokedex.name,
+            pokedex.description,
+            pokedex.num_entries,
+            pokedex.spritesheet_url,
+            pokedex.entries,
+            pokedex.revision,
+            pokedex.retired,
+            user_pokedex.visibility as 'visibility: Visibility'
+        from pokedex, user_pokedex
+        where
+            pokedex.id = ? and
+            user_pokedex.pokedex_id = pokedex.id and
+            user_pokedex.user_id = ?
+        ",
+        pokedex_id,
+        user_id
+    ).fetch_optional(db).await?;
+    let pokedex = pokedex.ok_or(AppError::NotFound)?;
+
+    // A pokedex can be shown or hidden regardless of the profile.
+    // Hidden pokedexes look like they don't exist.
+    if !is_visible(auth_session, user_id, pokedex.visibility.unwrap_or(user.visibility)).await? {
+        return Err(AppError::NotFound);
+    }
+
+    let collected: HashSet<i32> = query_scalar!(
+        "select entry_id from user_pokedex_progress where user_id = ? and pokedex_id = ?",
+        user_id,
+        pokedex_id
+    ).fetch_all(db).await?.into_iter().collect();
+
+    Ok(UserPokedex {
+        name: pokedex.name,
+        description: pokedex.description,
+        num_entries: pokedex.num_entries,
+        spritesheet_url: pokedex.spritesheet_url,
+        revision: pokedex.revision,
+        retired: pokedex.retired,
+        entries: parse_entries(pokedex.entries)?,
+        collected,
+        is_own_profile,
+        // Progress in retired pokedexes stays visible, but can't be changed anymore.
+        can_edit: can_edit && !pokedex.retired,
+    })
 }
 
 mod put {
     
     use super::*;
     use axum::extract::{Path, State};
-    use axum_login::AuthzBackend;
     use http::StatusCode;
     use sqlx::{query, query_scalar};
-    use tracing::info;
+    use tracing::{debug, info};
     use crate::auth::{AuthSession, Permission};
     use crate::web::AppState;
+    use axum::Json;
+    use crate::web::user::{audit_profile_edit, authorize_profile_edit, load_species_link};
 
     /// Add a new pokedex to a user's profile
+    #[utoipa::path(
+        put,
+        path = "/users/{username}/pokedexes/{pokedex_id}",
+        tag = "users",
+        params(("username" = String, Path), ("pokedex_id" = String, Path)),
+        responses(
+            (status = 200, description = "The pokedex was added"),
+            (status = 400, description = "The pokedex is already on the profile", body = ErrorBody),
+            (status = 401, body = ErrorBody),
+            (status = 404, description = "Unknown or retired pokedex", body = ErrorBody),
+        ),
+    )]
     pub async fn pokedex(
         Path((username, pokedex_id)): Path<(String, String)>,
         State(state): State<AppState>,
         auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
     ) -> Result<impl IntoResponse, AppError> {
         
         // Check user auth
-        let user = match auth_session.user {
-            None => return Err(AppError::Unauthorized),
-            Some(user) => user,
-        };
-        if user.name != username {
-            if !auth_session.backend.has_perm(&user, Permission::AddPokedexToOtherProfiles).await? {
-                // A user can't edit the profile of another used unless they have the 
-                // required permission.
-                return Err(AppError::Unauthorized);
-            }
+        let user = authorize_profile_edit(&auth_session, &username, Permission::AddPokedexToOtherProfiles).await?;
+
+        // Retired pokedexes can't be added anymore
+        let retired = query_scalar!("select retired from pokedex where id = ?", pokedex_id)
+            .fetch_optional(&state.database).await?;
+        if retired.unwrap_or(true) {
+            return Err(AppError::NotFound);
         }
         
         // Check if user already has this pokedex
@@ -61,23 +530,539 @@ mod put {
         
         // Insert the pokedex
         query!(
-            "insert into user_pokedex values ((select user.user_id from user where user.name = ?), ?)", 
+            "insert into user_pokedex (user_id, pokedex_id) values ((select user.user_id from user where user.name = ?), ?)", 
             username, 
             pokedex_id
         ).execute(&state.database).await?;
         
         info!("User {} has added pokedex {} to {}'s profile.", user.name, pokedex_id, username);
+        audit_profile_edit(&audit, &user, &username, AuditAction::PokedexAdd, &pokedex_id).await;
         
         Ok(StatusCode::OK)
     }
+
+    /// Mark an entry of a user's pokedex as collected.
+    /// Depending on the species link setting of the user, entries of the same species in
+    /// their other pokedexes are marked as well or offered to be marked.
+    #[utoipa::path(
+        put,
+        path = "/users/{username}/pokedexes/{pokedex_id}/entries/{entry_id}",
+        tag = "users",
+        params(("username" = String, Path), ("pokedex_id" = String, Path), ("entry_id" = i32, Path)),
+        responses(
+            (status = 200, body = MarkedEntry),
+            (status = 401, body = ErrorBody),
+            (status = 404, body = ErrorBody),
+            (status = 409, description = "The pokedex is retired", body = ErrorBody),
+        ),
+    )]
+    pub async fn entry(
+        Path((username, pokedex_id, entry_id)): Path<(String, String, i32)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+
+        // Check user auth
+        let user = authorize_profile_edit(&auth_session, &username, Permission::EditPokedexProgressOfOtherProfiles).await?;
+
+        // The entry has to be part of a pokedex on the user's profile
+        let (user_id, entries) = load_user_pokedex_entries(&state.database, &username, &pokedex_id).await?;
+        let entry = find_entry(&entries, entry_id).ok_or(AppError::NotFound)?;
+
+        // Marking an entry twice is not an error, it just stays collected.
+        let mut tx = state.database.begin().await?;
+        query!(
+            "insert ignore into user_pokedex_progress (user_id, pokedex_id, entry_id) values (?, ?, ?)",
+            user_id,
+            pokedex_id,
+            entry_id
+        ).execute(&mut *tx).await?;
+
+        // The setting of the profile owner decides, no matter who marks the entry.
+        let species_link = load_species_link(&mut *tx, user_id).await?;
+        let linked = match (species_link, &entry.species) {
+            (SpeciesLink::Off, _) | (_, None) => vec![],
+            (_, Some(species)) => LinkedPokedexes::load(&mut *tx, user_id, &pokedex_id).await?.find(species),
+        };
+        if species_link == SpeciesLink::Auto {
+            mark_linked_entries(&mut *tx, user_id, &pokedex_id, &linked).await?;
+        }
+        tx.commit().await?;
+
+        if user.name != username {
+            info!("User {} has marked entry {} of pokedex {} on {}'s profile as collected.", user.name, entry_id, pokedex_id, username);
+        } else {
+            debug!("User {} has marked entry {} of pokedex {} as collected.", user.name, entry_id, pokedex_id);
+        }
+        let details = format!("marked entry {} of pokedex {} as collected", entry_id, pokedex_id);
+        audit_profile_edit(&audit, &user, &username, AuditAction::ProgressEdit, &details).await;
+
+        Ok(Json(MarkedEntry { species_link, linked }))
+    }
+}
+
+mod post {
+
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::Json;
+    use tracing::info;
+    use crate::auth::{AuthSession, Permission};
+    use crate::web::user::{audit_profile_edit, authorize_profile_edit, load_species_link};
+
+    /// Mark many entries of a user's pokedex as collected or not collected at once.
+    /// Either all changes are applied or none of them.
+    /// If the user links species automatically, the added entries are marked in their
+    /// other pokedexes as well. Offering to mark them is left to single entries.
+    #[utoipa::path(
+        post,
+        path = "/users/{username}/pokedexes/{pokedex_id}/entries",
+        tag = "users",
+        params(("username" = String, Path), ("pokedex_id" = String, Path)),
+        request_body = BulkUpdate,
+        responses(
+            (status = 200, body = BulkUpdateSummary),
+            (status = 400, description = "Unknown entries or entries that are added and removed", body = ErrorBody),
+            (status = 401, body = ErrorBody),
+            (status = 404, body = ErrorBody),
+            (status = 409, description = "The pokedex is retired", body = ErrorBody),
+        ),
+    )]
+    pub async fn entries(
+        Path((username, pokedex_id)): Path<(String, String)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+        Json(update): Json<BulkUpdate>,
+    ) -> Result<impl IntoResponse, AppError> {
+
+        // Check user auth
+        let user = authorize_profile_edit(&auth_session, &username, Permission::EditPokedexProgressOfOtherProfiles).await?;
+
+        // Validate the whole request before touching the database.
+        // Every entry has to be part of the pokedex and may only be added or removed, not both.
+        let (user_id, entries) = load_user_pokedex_entries(&state.database, &username, &pokedex_id).await?;
+        let valid_ids: HashSet<i32> = entries.iter().flatten().map(|entry| entry.id).collect();
+        let add: HashSet<i32> = update.add.into_iter().collect();
+        let remove: HashSet<i32> = update.remove.into_iter().collect();
+        if !add.is_subset(&valid_ids) || !remove.is_subset(&valid_ids) || !add.is_disjoint(&remove) {
+            return Err(AppError::BadRequest);
+        }
+
+        let mut summary = BulkUpdateSummary {
+            added: vec![],
+            removed: vec![],
+            linked: vec![],
+        };
+        let mut tx = state.database.begin().await?;
+        for entry_id in add {
+            let result = query!(
+                "insert ignore into user_pokedex_progress (user_id, pokedex_id, entry_id) values (?, ?, ?)",
+                user_id,
+                pokedex_id,
+                entry_id
+            ).execute(&mut *tx).await?;
+            if result.rows_affected() > 0 {
+                summary.added.push(entry_id);
+            }
+        }
+        for entry_id in remove {
+            let result = query!(
+                "delete from user_pokedex_progress where user_id = ? and pokedex_id = ? and entry_id = ?",
+                user_id,
+                pokedex_id,
+                entry_id
+            ).execute(&mut *tx).await?;
+            if result.rows_affected() > 0 {
+                summary.removed.push(entry_id);
+            }
+        }
+        if !summary.added.is_empty() && load_species_link(&mut *tx, user_id).await? == SpeciesLink::Auto {
+            let mut linked_pokedexes = LinkedPokedexes::load(&mut *tx, user_id, &pokedex_id).await?;
+            for entry_id in &summary.added {
+                let Some(species) = find_entry(&entries, *entry_id).and_then(|entry| entry.species.as_ref()) else {
+                    continue;
+                };
+                let linked = linked_pokedexes.find(species);
+                mark_linked_entries(&mut *tx, user_id, &pokedex_id, &linked).await?;
+                // Several added entries may share a species, but each linked entry is only marked once.
+                for entry in &linked {
+                    linked_pokedexes.collected.insert((entry.pokedex_id.clone(), entry.entry_id));
+                }
+                summary.linked.extend(linked);
+            }
+        }
+        tx.commit().await?;
+
+        summary.added.sort();
+        summary.removed.sort();
+        info!(
+            "User {} has updated pokedex {} on {}'s profile: {} entries added, {} entries removed.",
+            user.name, pokedex_id, username, summary.added.len(), summary.removed.len()
+        );
+        let details = format!(
+            "updated pokedex {}: {} entries added, {} entries removed",
+            pokedex_id, summary.added.len(), summary.removed.len()
+        );
+        audit_profile_edit(&audit, &user, &username, AuditAction::ProgressEdit, &details).await;
+
+        Ok(Json(summary))
+    }
+
+    /// Mark the entries of the same species in the user's other pokedexes as collected.
+    /// This is how users that are offered linked entries accept the offer.
+    #[utoipa::path(
+        post,
+        path = "/users/{username}/pokedexes/{pokedex_id}/entries/{entry_id}/linked",
+        tag = "users",
+        params(("username" = String, Path), ("pokedex_id" = String, Path), ("entry_id" = i32, Path)),
+        responses(
+            (status = 200, description = "The entries that were marked", body = [LinkedEntry]),
+            (status = 401, body = ErrorBody),
+            (status = 404, description = "Unknown entry or entry without species", body = ErrorBody),
+            (status = 409, description = "The pokedex is retired", body = ErrorBody),
+        ),
+    )]
+    pub async fn linked(
+        Path((username, pokedex_id, entry_id)): Path<(String, String, i32)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+
+        // Check user auth
+        let user = authorize_profile_edit(&auth_session, &username, Permission::EditPokedexProgressOfOtherProfiles).await?;
+
+        let (user_id, entries) = load_user_pokedex_entries(&state.database, &username, &pokedex_id).await?;
+        let entry = find_entry(&entries, entry_id).ok_or(AppError::NotFound)?;
+        let species = entry.species.as_ref().ok_or(AppError::NotFound)?;
+
+        let mut tx = state.database.begin().await?;
+        let linked = LinkedPokedexes::load(&mut *tx, user_id, &pokedex_id).await?.find(species);
+        mark_linked_entries(&mut *tx, user_id, &pokedex_id, &linked).await?;
+        tx.commit().await?;
+
+        info!(
+            "User {} has marked {} entries linked to entry {} of pokedex {} on {}'s profile.",
+            user.name, linked.len(), entry_id, pokedex_id, username
+        );
+        let details = format!("marked {} entries linked to entry {} of pokedex {}", linked.len(), entry_id, pokedex_id);
+        audit_profile_edit(&audit, &user, &username, AuditAction::ProgressEdit, &details).await;
+        Ok(Json(linked))
+    }
+}
+
+mod delete {
+
+    use super::*;
+    use axum::extract::{Path, Query, State};
+    use http::StatusCode;
+    use sqlx::query_scalar;
+    use tracing::{debug, info};
+    use crate::auth::{AuthSession, Permission};
+    use crate::web::user::{audit_profile_edit, authorize_profile_edit};
+
+    /// Remove a pokedex from a user's profile
+    #[utoipa::path(
+        delete,
+        path = "/users/{username}/pokedexes/{pokedex_id}",
+        tag = "users",
+        params(("username" = String, Path), ("pokedex_id" = String, Path), RemovePokedexOptions),
+        responses(
+            (status = 200, description = "The pokedex was removed"),
+            (status = 401, body = ErrorBody),
+            (status = 404, body = ErrorBody),
+        ),
+    )]
+    pub async fn pokedex(
+        Path((username, pokedex_id)): Path<(String, String)>,
+        Query(options): Query<RemovePokedexOptions>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+
+        // Check user auth
+        let user = authorize_profile_edit(&auth_session, &username, Permission::RemovePokedexFromOtherProfiles).await?;
+
+        let user_id = query_scalar!("select user_id from user where name = ?", username)
+            .fetch_optional(&state.database).await?;
+        let user_id = user_id.ok_or(AppError::NotFound)?;
+
+        let mut tx = state.database.begin().await?;
+        let result = query!(
+            "delete from user_pokedex where user_id = ? and pokedex_id = ?",
+            user_id,
+            pokedex_id
+        ).execute(&mut *tx).await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+        if options.purge {
+            query!(
+                "delete from user_pokedex_progress where user_id = ? and pokedex_id = ?",
+                user_id,
+                pokedex_id
+            ).execute(&mut *tx).await?;
+        }
+        tx.commit().await?;
+
+        if options.purge {
+            info!("User {} has removed pokedex {} from {}'s profile and purged its progress.", user.name, pokedex_id, username);
+        } else {
+            info!("User {} has removed pokedex {} from {}'s profile.", user.name, pokedex_id, username);
+        }
+        let details = if options.purge { format!("{} along with its progress", pokedex_id) } else { pokedex_id };
+        audit_profile_edit(&audit, &user, &username, AuditAction::PokedexRemove, &details).await;
+
+        Ok(StatusCode::OK)
+    }
+
+    /// Mark an entry of a user's pokedex as not collected
+    #[utoipa::path(
+        delete,
+        path = "/users/{username}/pokedexes/{pokedex_id}/entries/{entry_id}",
+        tag = "users",
+        params(("username" = String, Path), ("pokedex_id" = String, Path), ("entry_id" = i32, Path)),
+        responses(
+            (status = 200, description = "The entry is not collected anymore"),
+            (status = 401, body = ErrorBody),
+            (status = 404, body = ErrorBody),
+            (status = 409, description = "The pokedex is retired", body = ErrorBody),
+        ),
+    )]
+    pub async fn entry(
+        Path((username, pokedex_id, entry_id)): Path<(String, String, i32)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+
+        // Check user auth
+        let user = authorize_profile_edit(&auth_session, &username, Permission::EditPokedexProgressOfOtherProfiles).await?;
+
+        // The entry has to be part of a pokedex on the user's profile
+        let (user_id, entries) = load_user_pokedex_entries(&state.database, &username, &pokedex_id).await?;
+        if !contains_entry(&entries, entry_id) {
+            return Err(AppError::NotFound);
+        }
+
+        query!(
+            "delete from user_pokedex_progress where user_id = ? and pokedex_id = ? and entry_id = ?",
+            user_id,
+            pokedex_id,
+            entry_id
+        ).execute(&state.database).await?;
+
+        if user.name != username {
+            info!("User {} has marked entry {} of pokedex {} on {}'s profile as not collected.", user.name, entry_id, pokedex_id, username);
+        } else {
+            debug!("User {} has marked entry {} of pokedex {} as not collected.", user.name, entry_id, pokedex_id);
+        }
+        let details = format!("marked entry {} of pokedex {} as not collected", entry_id, pokedex_id);
+        audit_profile_edit(&audit, &user, &username, AuditAction::ProgressEdit, &details).await;
+
+        Ok(StatusCode::OK)
+    }
 }
 
 mod get {
+    use axum::extract::{Path, Query, State};
     use axum::response::Html;
+    use axum::Json;
     use super::*;
-    
-    /// Get a user's pokedex progress
-    pub async fn pokedex() -> Result<impl IntoResponse, AppError> {
-        Ok(Html(""))
+
+    /// Get a user's pokedex progress.
+    /// The entries can be filtered through the query string, so filtered views can be bookmarked.
+    pub async fn pokedex(
+        Path((username, pokedex_id)): Path<(String, String)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Query(filter_query): Query<EntryFilterQuery>,
+        CsrfToken(csrf_token): CsrfToken,
+    ) -> Result<impl IntoResponse, AppError> {
+        let filter = EntryFilter::parse(&filter_query)?;
+        let pokedex = load_user_pokedex(&state.database, &auth_session, &username, &pokedex_id).await?;
+        let collected = pokedex.collected;
+
+        // Empty slots only make sense in the full layout, so a filtered view leaves them out.
+        let is_filtered = !filter.is_empty();
+        let entries: Vec<Option<EntryView>> = pokedex.entries
+            .into_iter()
+            .filter(|entry| match entry {
+                None => !is_filtered,
+                Some(entry) => filter.matches(entry, collected.contains(&entry.id)),
+            })
+            .map(|entry| entry.map(|entry| EntryView {
+                id: entry.id,
+                name: entry.name,
+                form: entry.form,
+                shiny: entry.shiny,
+                gmax: entry.gmax,
+                sprite_x: (entry.sprite % SPRITESHEET_WIDTH) * SPRITE_SIZE,
+                sprite_y: (entry.sprite / SPRITESHEET_WIDTH) * SPRITE_SIZE,
+                collected: collected.contains(&entry.id),
+            }))
+            .collect();
+        let num_matching = entries.iter().flatten().count();
+        let num_collected = entries.iter().flatten().filter(|entry| entry.collected).count();
+
+        Ok(Html(PokedexTemplate {
+            username,
+            pokedex_id,
+            pokedex_name: pokedex.name,
+            description: pokedex.description,
+            num_entries: pokedex.num_entries,
+            num_collected,
+            spritesheet_url: pokedex.spritesheet_url,
+            revision: pokedex.revision,
+            retired: pokedex.retired,
+            sprite_size: SPRITE_SIZE,
+            entries,
+            is_own_profile: pokedex.is_own_profile,
+            can_edit: pokedex.can_edit,
+            filter: filter_query,
+            is_filtered,
+            num_matching,
+            csrf_token,
+        }.render()?))
+    }
+
+    /// Get a user's pokedex progress as JSON.
+    /// This takes the same filter as the progress page. Empty slots are left out.
+    #[utoipa::path(
+        get,
+        path = "/users/{username}/pokedexes/{pokedex_id}",
+        tag = "users",
+        params(("username" = String, Path), ("pokedex_id" = String, Path), EntryFilterQuery),
+        responses(
+            (status = 200, body = UserPokedexView),
+            (status = 400, description = "Invalid filter", body = ErrorBody),
+            (status = 404, description = "Unknown or hidden user or pokedex", body = ErrorBody),
+        ),
+    )]
+    pub async fn pokedex_json(
+        Path((username, pokedex_id)): Path<(String, String)>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        Query(filter_query): Query<EntryFilterQuery>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let filter = EntryFilter::parse(&filter_query)?;
+        let pokedex = load_user_pokedex(&state.database, &auth_session, &username, &pokedex_id).await?;
+        let collected = pokedex.collected;
+
+        let entries: Vec<EntryProgress> = pokedex.entries
+            .into_iter()
+            .flatten()
+            .map(|entry| EntryProgress { collected: collected.contains(&entry.id), entry })
+            .filter(|progress| filter.matches(&progress.entry, progress.collected))
+            .collect();
+        Ok(Json(UserPokedexView {
+            id: pokedex_id,
+            name: pokedex.name,
+            description: pokedex.description,
+            revision: pokedex.revision,
+            retired: pokedex.retired,
+            num_entries: pokedex.num_entries,
+            num_collected: entries.iter().filter(|entry| entry.collected).count(),
+            can_edit: pokedex.can_edit,
+            entries,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::extract::Query;
+    use http::Uri;
+
+    use super::*;
+
+    /// Parses the filter from a query string, the way the progress page receives it.
+    fn filter(query: &str) -> Result<EntryFilter, AppError> {
+        let uri: Uri = format!("/?{}", query).parse().unwrap();
+        let Query(query) = Query::<EntryFilterQuery>::try_from_uri(&uri).unwrap();
+        EntryFilter::parse(&query)
+    }
+
+    fn entry(json: &str) -> PokedexEntry {
+        serde_json::from_str(json).unwrap()
+    }
+
+    #[test]
+    fn empty_filter_matches_everything() {
+        let filter = filter("name=&form=&shiny=&gmax=&gender=&status=").unwrap();
+        assert!(filter.is_empty());
+        let pikachu = entry(r#"{"id": 25, "name": "Pikachu", "form": "Cosplay", "sprite": 0, "shiny": true}"#);
+        assert!(filter.matches(&pikachu, false));
+        assert!(filter.matches(&pikachu, true));
+    }
+
+    #[test]
+    fn blank_text_does_not_filter() {
+        assert!(filter("name=++&form=+").unwrap().is_empty());
+    }
+
+    #[test]
+    fn text_matches_case_insensitive_parts() {
+        let filter = filter("name=+CHU+").unwrap();
+        assert!(!filter.is_empty());
+        assert!(filter.matches(&entry(r#"{"id": 25, "name": "Pikachu", "sprite": 0}"#), false));
+        assert!(filter.matches(&entry(r#"{"id": 26, "name": "Raichu", "sprite": 1}"#), false));
+        assert!(!filter.matches(&entry(r#"{"id": 1, "name": "Bulbasaur", "sprite": 2}"#), false));
+    }
+
+    #[test]
+    fn form_filter_skips_entries_without_form() {
+        let filter = filter("form=alola").unwrap();
+        assert!(filter.matches(&entry(r#"{"id": 26, "name": "Raichu", "form": "Alolan Form", "sprite": 0}"#), false));
+        assert!(!filter.matches(&entry(r#"{"id": 26, "name": "Raichu", "sprite": 0}"#), false));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn flags_match_exactly() {
+        let shiny = entry(r#"{"id": 25, "name": "Pikachu", "sprite": 0, "shiny": true}"#);
+        let gmax = entry(r#"{"id": 26, "name": "Pikachu", "sprite": 1, "gmax": true}"#);
+
+        let filter_shiny = filter("shiny=true").unwrap();
+        assert!(filter_shiny.matches(&shiny, false));
+        assert!(!filter_shiny.matches(&gmax, false));
+
+        let filter_no_gmax = filter("gmax=false").unwrap();
+        assert!(filter_no_gmax.matches(&shiny, false));
+        assert!(!filter_no_gmax.matches(&gmax, false));
+    }
+
+    #[test]
+    fn gender_filter_skips_entries_without_gender() {
+        let filter = filter("gender=female").unwrap();
+        assert!(filter.matches(&entry(r#"{"id": 25, "name": "Pikachu", "sprite": 0, "gender": "female"}"#), false));
+        assert!(!filter.matches(&entry(r#"{"id": 26, "name": "Pikachu", "sprite": 1, "gender": "male"}"#), false));
+        assert!(!filter.matches(&entry(r#"{"id": 27, "name": "Pikachu", "sprite": 2}"#), false));
+    }
+
+    #[test]
+    fn status_filters_by_progress() {
+        let pikachu = entry(r#"{"id": 25, "name": "Pikachu", "sprite": 0}"#);
+        assert!(filter("status=missing").unwrap().matches(&pikachu, false));
+        assert!(!filter("status=missing").unwrap().matches(&pikachu, true));
+        assert!(filter("status=collected").unwrap().matches(&pikachu, true));
+        assert!(!filter("status=collected").unwrap().matches(&pikachu, false));
+    }
+
+    #[test]
+    fn all_conditions_have_to_match() {
+        let filter = filter("name=pika&shiny=true&status=missing").unwrap();
+        assert!(filter.matches(&entry(r#"{"id": 25, "name": "Pikachu", "sprite": 0, "shiny": true}"#), false));
+        assert!(!filter.matches(&entry(r#"{"id": 25, "name": "Pikachu", "sprite": 0, "shiny": true}"#), true));
+        assert!(!filter.matches(&entry(r#"{"id": 26, "name": "Pikachu", "sprite": 1}"#), false));
+    }
+
+    #[test]
+    fn unknown_values_are_rejected() {
+        for query in ["shiny=yes", "gmax=1", "gender=unknown", "status=all"] {
+            assert!(matches!(filter(query), Err(AppError::BadRequest)), "{}", query);
+        }
+    }
+}
@@ -0,0 +1,474 @@
+use std::net::SocketAddr;
+use askama::Template;
+use axum::Router;
+use axum::routing::{get, post};
+use axum::response::{Html, IntoResponse, Redirect, Response};
+use axum::extract::{ConnectInfo, Path, State};
+use axum::{Extension, Form};
+use axum_messages::{Message, Messages};
+use serde::Deserialize;
+use sqlx::{query, query_scalar};
+use tracing::{error, info};
+
+use crate::error::AppError;
+use crate::web::AppState;
+use crate::web::audit::{AuditAction, AuditLog};
+use crate::web::csrf::CsrfToken;
+use crate::web::throttle::{self, LoginAttempt};
+use crate::web::user::{load_species_link, SpeciesLink, Visibility};
+use crate::auth::{AuthSession, Credentials, TokenScope, User, ADMIN_GROUP, MAX_API_TOKEN_NAME_LENGTH};
+
+#[derive(Template)]
+#[template(path = "settings.html")]
+struct SettingsTemplate {
+    messages: Vec<Message>,
+    username: String,
+    species_link: &'static str,
+    visibility: &'static str,
+    pokedexes: Vec<PokedexVisibilityView>,
+    tokens: Vec<ApiTokenView>,
+    /// A token that was just created. It can't be shown again later.
+    new_token: Option<String>,
+    /// Has to be sent along with requests that change something.
+    csrf_token: String,
+}
+
+/// An API token of the user, without the token itself.
+#[derive(Debug)]
+struct ApiTokenView {
+    token_id: i32,
+    name: String,
+    scope: &'static str,
+    creation_date: String,
+    last_used: Option<String>,
+}
+
+/// A Pokédex on the profile along with its visibility override.
+#[derive(Debug)]
+struct PokedexVisibilityView {
+    id: String,
+    name: String,
+    /// The name of the override or `default` if the Pokédex is as visible as the profile.
+    visibility: &'static str,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangePasswordForm {
+    current_password: String,
+    new_password: String,
+    new_password_confirmation: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ChangeUsernameForm {
+    username: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct SpeciesLinkForm {
+    species_link: SpeciesLink,
+}
+
+#[derive(Debug, Deserialize)]
+struct VisibilityForm {
+    visibility: Visibility,
+}
+
+#[derive(Debug, Deserialize)]
+struct PokedexVisibilityForm {
+    pokedex_id: String,
+    /// A visibility name or `default` to remove the override.
+    visibility: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct CreateTokenForm {
+    name: String,
+    scope: TokenScope,
+}
+
+#[derive(Debug, Deserialize)]
+struct DeleteAccountForm {
+    password: String,
+}
+
+pub fn router() -> Router<AppState> {
+    Router::new()
+        .route("/settings", get(get::settings))
+        .route("/settings/password", post(post::password))
+        .route("/settings/username", post(post::username))
+        .route("/settings/species-link", post(post::species_link))
+        .route("/settings/visibility", post(post::visibility))
+        .route("/settings/pokedex-visibility", post(post::pokedex_visibility))
+        .route("/settings/tokens", post(post::token))
+        .route("/settings/tokens/{token_id}/revoke", post(post::revoke_token))
+        .route("/settings/delete", post(post::delete))
+}
+
+/// Checks the password of the logged-in user through the auth backend.
+/// This goes through the same throttle as logging in, so a hijacked session can't be used
+/// to guess the password either. Locked out attempts are rejected like wrong passwords.
+async fn verify_password(
+    state: &AppState,
+    auth_session: &AuthSession,
+    address: SocketAddr,
+    username: &str,
+    password: String,
+) -> Result<bool, AppError> {
+    let attempt = LoginAttempt::new(username, address.ip());
+    if throttle::is_locked(&state.database, &attempt).await? {
+        return Ok(false);
+    }
+    let user = auth_session.authenticate(Credentials {
+        username: username.to_string(),
+        password,
+        next: None,
+        remember: None,
+    }).await?;
+    if user.is_none() {
+        throttle::record_failure(&state.database, &attempt).await?;
+        return Ok(false);
+    }
+    throttle::record_success(&state.database, &attempt).await?;
+    Ok(true)
+}
+
+/// Renders the settings of the logged-in user.
+async fn render_settings(
+    state: &AppState,
+    user: User,
+    messages: Messages,
+    csrf_token: String,
+    new_token: Option<String>,
+) -> Result<Response, AppError> {
+    let species_link = load_species_link(&state.database, user.user_id).await?;
+    let visibility = query_scalar!(
+        "select visibility as 'visibility: Visibility' from user where user_id = ?",
+        user.user_id
+    ).fetch_one(&state.database).await?;
+    let pokedexes = query!(
+        "
+        select pokedex.id, pokedex.name, user_pokedex.visibility as 'visibility: Visibility'
+        from pokedex, user_pokedex
+        where user_pokedex.user_id = ? and user_pokedex.pokedex_id = pokedex.id
+        order by pokedex.name
+        ",
+        user.user_id
+    ).fetch_all(&state.database).await?
+        .into_iter()
+        .map(|pokedex| PokedexVisibilityView {
+            id: pokedex.id,
+            name: pokedex.name,
+            visibility: pokedex.visibility.map_or("default", |visibility| visibility.name()),
+        })
+        .collect();
+    let tokens = query!(
+        "
+        select
+            token_id,
+            name,
+            scope as 'scope: TokenScope',
+            date_format(creation_date, '%Y-%m-%d %H:%i') as 'creation_date!',
+            date_format(last_used, '%Y-%m-%d %H:%i') as last_used
+        from api_token
+        where user_id = ?
+        order by creation_date
+        ",
+        user.user_id
+    ).fetch_all(&state.database).await?
+        .into_iter()
+        .map(|token| ApiTokenView {
+            token_id: token.token_id,
+            name: token.name,
+            scope: token.scope.name(),
+            creation_date: token.creation_date,
+            last_used: token.last_used,
+        })
+        .collect();
+    Ok(Html(SettingsTemplate {
+        messages: messages.into_iter().collect(),
+        username: user.name,
+        species_link: species_link.name(),
+        visibility: visibility.name(),
+        pokedexes,
+        tokens,
+        new_token,
+        csrf_token,
+    }.render()?).into_response())
+}
+
+mod get {
+
+    use super::*;
+
+    /// Shows the settings of the logged-in user.
+    pub async fn settings(
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        messages: Messages,
+        CsrfToken(csrf_token): CsrfToken,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = match auth_session.user {
+            None => return Ok(Redirect::to("/login?next=/settings").into_response()),
+            Some(user) => user,
+        };
+        render_settings(&state, user, messages, csrf_token, None).await
+    }
+}
+
+mod post {
+
+    use super::*;
+    use crate::auth::validate_username;
+
+    /// Changes the password of the logged-in user.
+    /// All other sessions of the user are logged out.
+    pub async fn password(
+        State(state): State<AppState>,
+        mut auth_session: AuthSession,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        Extension(audit): Extension<AuditLog>,
+        messages: Messages,
+        Form(form): Form<ChangePasswordForm>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.clone().ok_or(AppError::Unauthorized)?;
+
+        if !verify_password(&state, &auth_session, address, &user.name, form.current_password).await? {
+            messages.error("The current password is incorrect");
+            return Ok(Redirect::to("/settings"));
+        }
+        if let Err(message) = auth_session.backend.password_policy().check(&form.new_password) {
+            messages.error(message);
+            return Ok(Redirect::to("/settings"));
+        }
+        if form.new_password != form.new_password_confirmation {
+            messages.error("The passwords do not match");
+            return Ok(Redirect::to("/settings"));
+        }
+
+        let user = auth_session.backend.change_password(user.user_id, form.new_password).await?;
+        audit.record(AuditAction::PasswordChange, &user.name, None, None).await;
+
+        // The password change invalidated the session auth hash of every session.
+        // Log in again so that only the current session survives.
+        if let Err(err) = auth_session.login(&user).await {
+            error!("Failed to renew session after password change: {}", err);
+            return Ok(Redirect::to("/login"));
+        }
+
+        info!("User {} has changed their password.", user.name);
+        messages.success("Your password has been changed");
+        Ok(Redirect::to("/settings"))
+    }
+
+    /// Changes the name of the logged-in user.
+    pub async fn username(
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        messages: Messages,
+        Extension(audit): Extension<AuditLog>,
+        Form(form): Form<ChangeUsernameForm>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.ok_or(AppError::Unauthorized)?;
+
+        if let Err(message) = validate_username(&form.username) {
+            messages.error(message);
+            return Ok(Redirect::to("/settings"));
+        }
+
+        let result = query!(
+            "update user set name = ? where user_id = ?",
+            form.username,
+            user.user_id
+        ).execute(&state.database).await;
+        match result {
+            Ok(_) => {},
+            Err(sqlx::Error::Database(ref db_err)) if db_err.is_unique_violation() => {
+                messages.error(format!("The username {} is already taken", form.username));
+                return Ok(Redirect::to("/settings"));
+            },
+            Err(err) => return Err(err.into()),
+        }
+
+        info!("User {} has changed their name to {}.", user.name, form.username);
+        audit.record(AuditAction::UsernameChange, &user.name, Some(&form.username), None).await;
+        messages.success(format!("Your username is now {}", form.username));
+        Ok(Redirect::to("/settings"))
+    }
+
+    /// Changes how marking an entry affects the other Pokédexes of the logged-in user.
+    pub async fn species_link(
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        messages: Messages,
+        Form(form): Form<SpeciesLinkForm>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.ok_or(AppError::Unauthorized)?;
+
+        query!(
+            "update user set species_link = ? where user_id = ?",
+            form.species_link,
+            user.user_id
+        ).execute(&state.database).await?;
+
+        info!("User {} has set species linking to {}.", user.name, form.species_link.name());
+        messages.success("Your species linking setting has been saved");
+        Ok(Redirect::to("/settings"))
+    }
+
+    /// Changes who may see the profile of the logged-in user.
+    pub async fn visibility(
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        messages: Messages,
+        Form(form): Form<VisibilityForm>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.ok_or(AppError::Unauthorized)?;
+
+        query!(
+            "update user set visibility = ? where user_id = ?",
+            form.visibility,
+            user.user_id
+        ).execute(&state.database).await?;
+
+        info!("User {} has set the visibility of their profile to {}.", user.name, form.visibility.name());
+        messages.success("Your profile visibility has been saved");
+        Ok(Redirect::to("/settings"))
+    }
+
+    /// Overrides the visibility of a single Pokédex on the profile of the logged-in user.
+    pub async fn pokedex_visibility(
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        messages: Messages,
+        Form(form): Form<PokedexVisibilityForm>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.ok_or(AppError::Unauthorized)?;
+
+        let visibility = match form.visibility.as_str() {
+            "default" => None,
+            name => Some(Visibility::from_name(name).ok_or(AppError::BadRequest)?),
+        };
+        let result = query!(
+            "update user_pokedex set visibility = ? where user_id = ? and pokedex_id = ?",
+            visibility,
+            user.user_id,
+            form.pokedex_id
+        ).execute(&state.database).await?;
+        if result.rows_affected() == 0 {
+            // Either the pokedex is not on the profile or the visibility did not change.
+            let is_on_profile = query_scalar!(
+                "select count(*) from user_pokedex where user_id = ? and pokedex_id = ?",
+                user.user_id,
+                form.pokedex_id
+            ).fetch_one(&state.database).await? > 0;
+            if !is_on_profile {
+                return Err(AppError::NotFound);
+            }
+        }
+
+        info!("User {} has set the visibility of pokedex {} to {}.", user.name, form.pokedex_id, form.visibility);
+        messages.success("The visibility of the Pokédex has been saved");
+        Ok(Redirect::to("/settings"))
+    }
+
+    /// Creates a new API token for the logged-in user.
+    /// The token is shown right away, since only its hash is stored.
+    pub async fn token(
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        messages: Messages,
+        Extension(audit): Extension<AuditLog>,
+        CsrfToken(csrf_token): CsrfToken,
+        Form(form): Form<CreateTokenForm>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.clone().ok_or(AppError::Unauthorized)?;
+
+        let name = form.name.trim();
+        if name.is_empty() || name.chars().count() > MAX_API_TOKEN_NAME_LENGTH {
+            messages.error("The token name must be between 1 and 128 characters long");
+            return Ok(Redirect::to("/settings").into_response());
+        }
+
+        let token = auth_session.backend.create_api_token(user.user_id, name, form.scope).await?;
+        info!("User {} has created the API token {} with scope {}.", user.name, name, form.scope.name());
+        audit.record(AuditAction::ApiTokenCreate, &user.name, Some(name), Some(form.scope.name())).await;
+        render_settings(&state, user, messages, csrf_token, Some(token)).await
+    }
+
+    /// Revokes an API token of the logged-in user.
+    pub async fn revoke_token(
+        Path(token_id): Path<i32>,
+        State(state): State<AppState>,
+        auth_session: AuthSession,
+        messages: Messages,
+        Extension(audit): Extension<AuditLog>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.ok_or(AppError::Unauthorized)?;
+
+        let result = query!(
+            "delete from api_token where token_id = ? and user_id = ?",
+            token_id,
+            user.user_id
+        ).execute(&state.database).await?;
+        if result.rows_affected() == 0 {
+            return Err(AppError::NotFound);
+        }
+
+        info!("User {} has revoked API token {}.", user.name, token_id);
+        audit.record(AuditAction::ApiTokenRevoke, &user.name, Some(&token_id.to_string()), None).await;
+        messages.success("The API token has been revoked");
+        Ok(Redirect::to("/settings"))
+    }
+
+    /// Deletes the account of the logged-in user along with all of their data.
+    pub async fn delete(
+        State(state): State<AppState>,
+        mut auth_session: AuthSession,
+        ConnectInfo(address): ConnectInfo<SocketAddr>,
+        messages: Messages,
+        Form(form): Form<DeleteAccountForm>,
+    ) -> Result<impl IntoResponse, AppError> {
+        let user = auth_session.user.clone().ok_or(AppError::Unauthorized)?;
+
+        if !verify_password(&state, &auth_session, address, &user.name, form.password).await? {
+            messages.error("The password is incorrect");
+            return Ok(Redirect::to("/settings"));
+        }
+
+        let mut tx = state.database.begin().await?;
+
+        // Someone has to be left to administrate the instance.
+        let admins = query_scalar!(
+            "select user_id from user_group where `group` = ? for update",
+            ADMIN_GROUP
+        ).fetch_all(&mut *tx).await?;
+        if admins == [user.user_id] {
+            messages.error("You are the last administrator and can't delete your account");
+            return Ok(Redirect::to("/settings"));
+        }
+
+        query!("delete from user_pokedex_progress where user_id = ?", user.user_id)
+            .execute(&mut *tx).await?;
+        query!("delete from orphaned_pokedex_progress where user_id = ?", user.user_id)
+            .execute(&mut *tx).await?;
+        query!("delete from user_pokedex where user_id = ?", user.user_id)
+            .execute(&mut *tx).await?;
+        query!("delete from user_group where user_id = ?", user.user_id)
+            .execute(&mut *tx).await?;
+        query!("delete from api_token where user_id = ?", user.user_id)
+            .execute(&mut *tx).await?;
+        query!("delete from user where user_id = ?", user.user_id)
+            .execute(&mut *tx).await?;
+        tx.commit().await?;
+
+        info!("User {} has deleted their account.", user.name);
+
+        // The user does not exist anymore, so there is nothing left to log out from
+        // if this fails. The session will be rejected on the next request either way.
+        if let Err(err) = auth_session.logout().await {
+            error!("Failed to log out deleted user {}: {}", user.name, err);
+        }
+        Ok(Redirect::to("/"))
+    }
+}